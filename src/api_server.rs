@@ -2,6 +2,9 @@ use process::*;
 
 use std::str::FromStr;
 use std::error::Error;
+use store::valid_name;
+use index::IndexEntry;
+use delta::{state_at, local_content_of};
 use unicase::UniCase;
 
 use rustful::{
@@ -47,6 +50,26 @@ impl Handler for Api {
 }
 
 
+/* route param used to build store path, rejected unless it's plain name */
+fn name_param(context: &Context, param: &str, default: &str) -> Result<String, String> {
+    let value = match context.variables.get(param) {
+        Some(value) => value.to_string(),
+        None => default.to_string(),
+    };
+    if valid_name(&value) {
+        Ok(value)
+    } else {
+        Err(format!("Invalid {}: '{}'", param, value))
+    }
+}
+
+
+fn bad_request(mut response: Response, error: String) {
+    response.set_status(StatusCode::BadRequest);
+    response.send(format!("{{\"error\": {}}}", json::encode(&error).unwrap_or(String::from("\"\""))));
+}
+
+
 fn index_page(context: Context, response: Response) {
     let username = match context.variables.get("username") {
         Some(name) => name,
@@ -58,13 +81,9 @@ fn index_page(context: Context, response: Response) {
 
 /* HTTP path with params: /diff/:hostname/:username/:uuid1/:uuid2 */
 fn chgset_diff_page(context: Context, response: Response) {
-    let hostname = match context.variables.get("hostname") {
-        Some(name) => name.to_string(),
-        None => "s0".to_string(),
-    };
-    let username = match context.variables.get("username") {
-        Some(name) => name.to_string(),
-        None => "nobody".to_string(),
+    let (hostname, username) = match (name_param(&context, "hostname", "s0"), name_param(&context, "username", "nobody")) {
+        (Ok(hostname), Ok(username)) => (hostname, username),
+        (Err(err), _) | (_, Err(err)) => return bad_request(response, err),
     };
     let uuid1 = match context.variables.get("uuid1") {
         Some(uuid) => Uuid::from_str(uuid.to_string().as_ref()).unwrap_or(root_failed_parse_from_string_to_uuid_uuid()),
@@ -76,32 +95,18 @@ fn chgset_diff_page(context: Context, response: Response) {
     };
     debug!("Params for chgset_diff_page: hn: {}, un: {}, uuid1: {}, uuid2: {}", hostname, username, uuid1, uuid2);
 
-    let mut chsets = all_changesets(username.clone()).into_iter()
-        .filter(|e| e.uuid != uuid1 || e.uuid != uuid2);
-
-    let a = match chsets.next() {
-        Some(next_one) => next_one,
+    /* both sides are whole states, materialized from chains of changesets like in diff command */
+    let store = SpiceStore::of_host(&hostname);
+    let a = match state_at(&store, &username, uuid1) {
+        Some(changeset) => changeset,
         None => Changeset { uuid: root_failed_no_next_item_uuid(), parent: root_failed_no_next_item_uuid(), .. Default::default() },
     };
-    let a_local_content: Vec<u8> = a.clone()
-        .entries
-        .into_iter()
-        // .filter(|f| f.file.path.ends_with(".php") )
-        .flat_map(|f| f.file.local_content )
-        .collect();
-    debug!("A: local content DBG: {:?}", String::from_utf8(a_local_content.clone()));
-
-    let b = match chsets.next() {
-        Some(next_one) => next_one,
+    let b = match state_at(&store, &username, uuid2) {
+        Some(changeset) => changeset,
         None => Changeset { uuid: root_failed_no_next_item_uuid(), parent: root_failed_no_next_item_uuid(), .. Default::default() },
     };
-    let b_local_content: Vec<u8> = b.clone()
-        .entries
-        .into_iter()
-        // .filter(|f| f.file.path.ends_with(".php") )
-        .flat_map(|f| f.file.local_content )
-        .collect();
-    debug!("B local content DBG: {:?}", String::from_utf8(b_local_content.clone()));
+    debug!("A: local content DBG: {:?}", local_content_of(&a));
+    debug!("B local content DBG: {:?}", local_content_of(&b));
 
     /*
         TODO:
@@ -114,37 +119,76 @@ fn chgset_diff_page(context: Context, response: Response) {
 
     print_difference(
         calculate_difference(
-            String::from_utf8(a_local_content).unwrap(),
-            String::from_utf8(b_local_content).unwrap(),
+            local_content_of(&a),
+            local_content_of(&b),
             ""));
 
     response.send(format!("[{},{}]", a.to_string(), b.to_string()));
 }
 
 
+/* HTTP path with params: /chgset/:hostname/:username/:uuid1 */
 fn chgset_show_page(context: Context, response: Response) {
-    let user = match context.variables.get("username") {
-        Some(name) => name,
-        None => "nobody".into(),
+    let (host, user) = match (name_param(&context, "hostname", "s0"), name_param(&context, "username", "nobody")) {
+        (Ok(host), Ok(user)) => (host, user),
+        (Err(err), _) | (_, Err(err)) => return bad_request(response, err),
+    };
+    let uuid = match context.variables.get("uuid1") {
+        Some(uuid) => Uuid::from_str(uuid.to_string().as_ref()).unwrap_or(root_failed_parse_from_string_to_uuid_uuid()),
+        None => root_failed_no_uuud_given_uuid(),
     };
-    let all = all_changesets(user.to_string())
-        .into_iter()
-        .map(|e| e.to_string() + "<br/><hr>")
-        .collect::<String>();
-    response.send(format!("<div>Listing timestamp sorted history:<br/>{}</div>", all));
+    /* decodes only shown changeset, found through index */
+    match SpiceStore::of_host(&host).changeset(&user, uuid) {
+        Some(changeset) => response.send(changeset.to_string()),
+        None => response.send(format!("{{\"error\": \"No changeset: {}\"}}", uuid)),
+    }
+}
+
+
+/* HTTP path with params: /state/:hostname/:username/:domain */
+fn domain_state_page(context: Context, response: Response) {
+    let (host, user) = match (name_param(&context, "hostname", "s0"), name_param(&context, "username", "nobody")) {
+        (Ok(host), Ok(user)) => (host, user),
+        (Err(err), _) | (_, Err(err)) => return bad_request(response, err),
+    };
+    let domain = match name_param(&context, "domain", "localhost") {
+        Ok(domain) => domain,
+        Err(err) => return bad_request(response, err),
+    };
+    match SpiceStore::of_host(&host).domain_state(&user, &domain) {
+        Some(state) => response.send(state.to_string()),
+        None => response.send(format!("{{\"error\": \"No state of domain: {}\"}}", domain)),
+    }
 }
 
 
+/* HTTP path with params: /history/:hostname/:username(/:uuid1) */
 fn chgset_history_page(context: Context, response: Response) {
-    let user = match context.variables.get("username") {
-        Some(name) => name,
-        None => "nobody".into(),
+    let (host, user) = match (name_param(&context, "hostname", "s0"), name_param(&context, "username", "nobody")) {
+        (Ok(host), Ok(user)) => (host, user),
+        (Err(err), _) | (_, Err(err)) => return bad_request(response, err),
+    };
+    /* summaries are read from index, without decoding any changeset */
+    let index = match SpiceStore::of_host(&host).index(&user) {
+        Ok(index) => index,
+        Err(err) => {
+            response.send(format!("{{\"error\": {}}}", json::encode(&err).unwrap_or(String::from("\"\""))));
+            return
+        },
     };
-    let all = all_changesets(user.to_string())
-        .into_iter()
-        .map(|e| e.to_string() + "<br/><hr>")
-        .collect::<String>();
-    response.send(format!("<div>Listing timestamp sorted history:<br/>{}</div>", all));
+    /* given changeset ends listed history */
+    let until = match context.variables.get("uuid1") {
+        Some(uuid) => {
+            let uuid = Uuid::from_str(uuid.to_string().as_ref()).unwrap_or(root_failed_parse_from_string_to_uuid_uuid());
+            match index.iter().find(|entry| entry.uuid == uuid) {
+                Some(entry) => entry.timestamp,
+                None => return response.send(format!("{{\"error\": \"No changeset: {}\"}}", uuid)),
+            }
+        },
+        None => u64::max_value(),
+    };
+    let history: Vec<&IndexEntry> = index.iter().filter(|entry| entry.timestamp <= until).collect();
+    response.send(json::encode(&history).unwrap_or(String::from("[]")));
 }
 
 
@@ -153,7 +197,7 @@ fn chgset_history_page(context: Context, response: Response) {
  */
 pub fn start() {
     let server_result = Server {
-        host: root_default_http_port().into(), /*Turn a port number into an IPV4 host address (0.0.0.0:8080 in this case). */
+        host: config().http_port.into(), /*Turn a port number into an IPV4 host address (0.0.0.0:8080 in this case). */
         handlers: insert_routes!{
             /* route scenarios */
             TreeRouter::new() => {
@@ -166,6 +210,9 @@ pub fn start() {
                 /* show details of given changeset. */
                 "/chgset/:hostname/:username/:uuid1" => Get: Api(Some(chgset_show_page)),
 
+                /* current state of given domain of specified user on specified host: */
+                "/state/:hostname/:username/:domain" => Get: Api(Some(domain_state_page)),
+
                 /* diff changesets with given uuids of specified user on specified host: */
                 "/diff/:hostname/:username/:uuid1/:uuid2" => Get: Api(Some(chgset_diff_page)),
 
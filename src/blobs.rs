@@ -0,0 +1,198 @@
+use process::*;
+
+use std::fs::{rename, remove_file};
+
+
+/* unreferenced blobs younger than this (in seconds) are kept, they may belong to scan that didn't store its changeset yet */
+pub const BLOB_GRACE_PERIOD: i64 = 86400;
+
+
+/* blobs are spread over 256 subdirs, by first byte of their checksum */
+pub fn blob_file(blobs_dir: &str, sha256: &str) -> String {
+    format!("{}/{}/{}", blobs_dir, &sha256[..2], sha256)
+}
+
+
+/* refreshes modification time of reused blob by writing its first byte again, so gc grace period covers it too */
+fn touch_blob(file_name: &str) -> Result<(), String> {
+    let touched = OpenOptions::new().read(true).write(true).open(file_name)
+        .and_then(|mut file| {
+            let mut first = [0u8; 1];
+            try!(file.read_exact(&mut first));
+            try!(file.seek(SeekFrom::Start(0)));
+            file.write_all(&first)
+        });
+    match touched {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to touch blob: {}. Cause: {}", file_name, err)),
+    }
+}
+
+
+/* stores compressed content under its sha256 checksum, only if same content wasn't stored before */
+pub fn store_blob(blobs_dir: &str, content: &[u8]) -> Result<String, String> {
+    let sha256 = match raw_checksums_of(content) {
+        Ok((_, sha256)) => sha256,
+        Err(err) => return Err(format!("Failed to checksum blob. Cause: {}", err)),
+    };
+    let file_name = blob_file(blobs_dir, &sha256);
+    if Path::new(&file_name).exists() {
+        debug!("Blob: {} already stored", sha256);
+        return touch_blob(&file_name).map(|_| sha256)
+    }
+    match Path::new(&file_name).parent() {
+        Some(dir) => match create_dir_all(dir) {
+            Ok(_) => {},
+            Err(err) => return Err(format!("Failed to create dir: {:?}. Cause: {}", dir, err)),
+        },
+        None => {},
+    }
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::Best);
+    match zlib.write_all(content) {
+        Ok(_) => {},
+        Err(err) => return Err(format!("Failed to compress blob: {}. Cause: {}", sha256, err)),
+    }
+    let compressed_bytes = match zlib.finish() {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(format!("Failed to compress blob: {}. Cause: {}", sha256, err)),
+    };
+    /* same content might be stored by scans of other users at the same time */
+    let temp_file = format!("{}.{}.tmp", file_name, Uuid::new_v4());
+    let written = File::create(&temp_file)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            writer.write_all(&compressed_bytes).and_then(|_| writer.flush())
+        })
+        .and_then(|_| rename(&temp_file, &file_name));
+    match written {
+        Ok(_) => Ok(sha256),
+        Err(err) => {
+            remove_file(&temp_file).unwrap_or(());
+            Err(format!("Failed to write blob: {}. Cause: {}", file_name, err))
+        },
+    }
+}
+
+
+pub fn load_blob(blobs_dir: &str, sha256: &str) -> Result<Vec<u8>, String> {
+    if sha256.len() < 2 {
+        return Err(format!("Invalid blob checksum: '{}'", sha256))
+    }
+    let file_name = blob_file(blobs_dir, sha256);
+    match File::open(&file_name) {
+        Ok(file) => {
+            let mut content = vec!();
+            match ZlibDecoder::new(BufReader::new(file)).read_to_end(&mut content) {
+                Ok(_) => Ok(content),
+                Err(err) => Err(format!("Failed to decompress blob: {}. Cause: {}", file_name, err)),
+            }
+        },
+        Err(err) => Err(format!("Failed to open blob: {}. Cause: {}", file_name, err)),
+    }
+}
+
+
+/* local content of file, read from blob store. Empty if it can't be loaded */
+pub fn content_of(file: &FileEntry) -> Vec<u8> {
+    if file.content_sha256.is_empty() {
+        return vec!()
+    }
+    match load_blob(&config().blobs_dir, &file.content_sha256) {
+        Ok(content) => content,
+        Err(err) => {
+            warn!("No content of file: {}. {}", file.path, err);
+            vec!()
+        },
+    }
+}
+
+
+/* number of file entries referencing each blob */
+pub fn blob_references(changesets: &[Changeset]) -> HashMap<String, usize> {
+    let mut references = HashMap::new();
+    for file in changesets.iter().flat_map(|changeset| changeset.entries.iter()).flat_map(|domain| domain.files.iter()) {
+        if !file.content_sha256.is_empty() {
+            *references.entry(file.content_sha256.clone()).or_insert(0) += 1;
+        }
+    }
+    references
+}
+
+
+/*
+    removes blobs without any references, modified more than grace_period seconds ago. Returns numbers of removed and kept blobs.
+    Temporary files of blobs, left behind by interrupted scans, are removed after the same grace period.
+ */
+pub fn collect_garbage(blobs_dir: &str, references: &HashMap<String, usize>, grace_period: i64) -> Result<(usize, usize), String> {
+    let now = get_time().sec;
+    let (mut removed, mut kept) = (0, 0);
+    let walker = WalkDir::new(blobs_dir)
+        .follow_links(false)
+        .min_depth(2)
+        .max_depth(2)
+        .into_iter();
+
+    for entry in walker
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file()) {
+        let name = entry.file_name().to_str().unwrap_or("").to_string();
+        if references.get(&name).map(|count| *count > 0).unwrap_or(false) {
+            kept += 1;
+            continue
+        }
+        match entry.metadata() {
+            Ok(ref metadata) if now - metadata.mtime() >= grace_period => {},
+            _ => { /* recent or unknown age */
+                kept += 1;
+                continue
+            },
+        }
+        match remove_file(entry.path()) {
+            Ok(_) => {
+                debug!("Removed unreferenced blob: {}", name);
+                removed += 1;
+            },
+            Err(err) => return Err(format!("Failed to remove blob: {:?}. Cause: {}", entry.path(), err)),
+        }
+    }
+    Ok((removed, kept))
+}
+
+
+#[cfg(test)]
+#[test]
+fn store_blob_test() {
+    let blobs_dir = "/tmp/yak-blobs-test";
+    remove_dir_all(blobs_dir).unwrap_or(());
+
+    let content = "<?php echo INDEX phpinfo();".as_bytes();
+    let sha256 = store_blob(blobs_dir, content).unwrap();
+    assert!(store_blob(blobs_dir, content).unwrap() == sha256, "Same content should be stored once");
+    assert!(load_blob(blobs_dir, &sha256).unwrap() == content);
+    let other = store_blob(blobs_dir, "<?php echo MAIN phpinfo();".as_bytes()).unwrap();
+    assert!(other != sha256);
+    assert!(load_blob(blobs_dir, "deadbeef").is_err());
+    let big_content = vec![b'a'; 300000];
+    let big = store_blob(blobs_dir, &big_content).unwrap();
+    assert!(load_blob(blobs_dir, &big).unwrap() == big_content);
+
+    let changeset = Changeset {
+        entries: vec!(DomainEntry {
+            files: vec!(
+                FileEntry { path: String::from("/a/index.php"), content_sha256: sha256.clone(), .. Default::default() },
+                FileEntry { path: String::from("/b/index.php"), content_sha256: sha256.clone(), .. Default::default() },
+            ),
+            .. Default::default()
+        }),
+        .. Default::default()
+    };
+    let references = blob_references(&[changeset]);
+    assert!(references.get(&sha256) == Some(&2));
+    let stale = format!("{}.{}.tmp", blob_file(blobs_dir, &other), Uuid::new_v4());
+    File::create(&stale).unwrap();
+    assert!(collect_garbage(blobs_dir, &references, BLOB_GRACE_PERIOD) == Ok((0, 4)), "Recent blobs should be kept");
+    assert!(collect_garbage(blobs_dir, &references, 0) == Ok((3, 1)));
+    assert!(!Path::new(&stale).exists(), "Stale temporary file should be removed");
+    assert!(load_blob(blobs_dir, &other).is_err());
+    assert!(load_blob(blobs_dir, &sha256).is_ok());
+}
@@ -0,0 +1,727 @@
+use process::*;
+use webapps::webapp_type_of;
+use spice::{gather_baseline, store_baseline};
+use states::{verdict_of, severity_level_of};
+use delta::{state_at, local_content_of, current_state};
+use blobs::{blob_references, collect_garbage, BLOB_GRACE_PERIOD};
+use retention::{retained, prune_chain};
+use index::IndexEntry;
+use query::{run_query, rebuild_query_store};
+
+
+/* process exit codes returned by each subcommand: */
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NOT_FOUND: i32 = 3;
+pub const EXIT_CORRUPTED: i32 = 4;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Scan(Vec<String>, Option<SeverityLevels>, bool), /* list of user names to scan (empty means: all users), severity level, full rescan */
+    Serve,
+    History(String, bool, Option<u64>, Option<u64>), /* user name, json output, since and until timestamps */
+    Show(String, Uuid, bool),       /* user name, changeset uuid, delta only */
+    Diff(String, Uuid, Uuid),       /* user name, changeset uuids */
+    Verify(Vec<String>),            /* list of user names to verify, empty means: all users */
+    Domains(Option<WebAppTypes>, bool, Vec<String>), /* web app type filter, outdated only, list of user names */
+    Spice(WebAppTypes, String, String, String, bool), /* web app type, version, release dir, minimal interpreter, supported */
+    Threats(Vec<String>),           /* list of user names, empty means: all users */
+    Gc,
+    Prune(Vec<String>),             /* list of user names, empty means: all users */
+    State(String, String),          /* user name, domain name */
+    Reindex(Vec<String>),           /* list of user names, empty means: all users */
+    Query(Option<String>, bool),    /* SQL query, rebuild query store first */
+    Help,
+}
+
+
+pub fn usage() -> String {
+    vec!(
+        "Usage: yak [--config FILE] [command] [options]",
+        "",
+        "Configuration is read from: --config FILE, $YAK_CONFIG or /etc/yak.json (if exists).",
+        "Each config value may be overriden by environment variable, f.e.: YAK_HTTP_PORT=3001",
+        "",
+        "Commands:",
+        "    scan [--user NAME].. [--severity LEVEL] [--full]",
+        "                                      traverse home dirs and store changesets (default). LEVEL: normal, pedantic, psycho",
+        "                                      files unchanged since previous changeset are not read again, unless --full given",
+        "    serve                             start Http service (aliases: api, www, web, server, s)",
+        "    history USER [--json] [--since TIMESTAMP] [--until TIMESTAMP]",
+        "                                      list timestamp sorted changesets of given user (optionally in time window, in miliseconds)",
+        "    state USER DOMAIN                 show current state of given domain",
+        "    show USER UUID [--delta]          show state of user at given changeset (or only changes stored in it, with --delta)",
+        "    diff USER UUID1 UUID2             diff local content of states at two changesets",
+        "    verify [USER]..                   check that all stored changesets are decodable and linked to their parents (corrupted are quarantined)",
+        "    domains [--type TYPE] [--outdated] [USER]..",
+        "                                      list domains with detected web app type, version and health verdict (f.e.: --type wordpress)",
+        "    spice TYPE VERSION DIR [--interpreter VERSION] [--unsupported]",
+        "                                      learn checksums of pristine web app release extracted to DIR",
+        "    threats [USER]..                  list files with signature hits or suspicion score above threshold",
+        "    prune [USER]..                    remove changesets not kept by retention policy (keep_last, keep_daily, keep_weekly)",
+        "    gc                                remove stored file contents not referenced by any changeset",
+        "    reindex [USER]..                  rebuild index of changesets from stored files",
+        "    query [--rebuild] [SQL]           query current scan results (tables: users, domains, files, probes, findings)",
+        "                                      f.e.: yak query \"SELECT user, name FROM domains WHERE app_type = 'WordPress' AND version_major < 5\"",
+        "                                      domains.version_key holds zero padded version, for ordering and range comparisons",
+        "                                      available only when built with: --features query",
+        "    help                              show this message",
+        "",
+        "Exit codes:",
+        "    0 - success, 1 - failure, 2 - invalid usage, 3 - changeset not found, 4 - corrupted changesets found",
+    ).join("\n")
+}
+
+
+fn parse_timestamp(value: Option<&String>, option: &str) -> Result<u64, String> {
+    match value {
+        Some(value) => match value.parse() {
+            Ok(timestamp) => Ok(timestamp),
+            Err(_) => Err(format!("Invalid timestamp: '{}' for option: {}", value, option)),
+        },
+        None => Err(format!("Missing value for option: {}", option)),
+    }
+}
+
+
+fn parse_uuid(value: &str) -> Result<Uuid, String> {
+    match Uuid::parse_str(value) {
+        Ok(uuid) => Ok(uuid),
+        Err(err) => Err(format!("Invalid UUID: '{}'. Cause: {:?}", value, err)),
+    }
+}
+
+
+fn positional(args: &[String], index: usize, name: &str) -> Result<String, String> {
+    match args.get(index) {
+        Some(value) if !value.starts_with("-") => Ok(value.clone()),
+        _ => Err(format!("Missing argument: {}", name)),
+    }
+}
+
+
+/* removes global --config FILE option from given args and returns its value */
+pub fn extract_config_option(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    let position = args.iter().position(|e| e == "--config" || e == "-c");
+    match position {
+        Some(index) => {
+            if index + 1 >= args.len() {
+                return Err(String::from("Missing value for option: --config"))
+            }
+            let path = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(path))
+        },
+        None => Ok(None),
+    }
+}
+
+
+/* args - command line arguments without program name */
+pub fn parse_arguments(args: &[String]) -> Result<Command, String> {
+    let command = match args.first() {
+        Some(cmd) => cmd.as_str(),
+        None => return Ok(Command::Scan(vec!(), None, false)), /* keep cron jobs working without arguments */
+    };
+    let rest = &args[1..];
+    match command {
+        "scan" => {
+            let mut users = vec!();
+            let mut severity = None;
+            let mut full = false;
+            let mut options = rest.iter();
+            while let Some(option) = options.next() {
+                match option.as_str() {
+                    "--user" | "-u" => {
+                        match options.next() {
+                            Some(user) => users.push(user.clone()),
+                            None => return Err(String::from("Missing value for option: --user")),
+                        }
+                    },
+                    "--severity" => {
+                        match options.next() {
+                            Some(name) => match severity_level_of(name) {
+                                Some(level) => severity = Some(level),
+                                None => return Err(format!("Unknown severity level: '{}'", name)),
+                            },
+                            None => return Err(String::from("Missing value for option: --severity")),
+                        }
+                    },
+                    "--full" => full = true,
+                    unknown => return Err(format!("Unknown option for scan: '{}'", unknown)),
+                }
+            }
+            Ok(Command::Scan(users, severity, full))
+        },
+
+        "serve" | "api" | "www" | "web" | "server" | "s" => {
+            match rest.first() {
+                Some(unknown) => Err(format!("Unknown option for serve: '{}'", unknown)),
+                None => Ok(Command::Serve),
+            }
+        },
+
+        "history" => {
+            let user = try!(positional(rest, 0, "USER"));
+            let mut json_output = false;
+            let mut since = None;
+            let mut until = None;
+            let mut options = rest.iter().skip(1);
+            while let Some(option) = options.next() {
+                match option.as_str() {
+                    "--json" => json_output = true,
+                    "--since" => since = Some(try!(parse_timestamp(options.next(), "--since"))),
+                    "--until" => until = Some(try!(parse_timestamp(options.next(), "--until"))),
+                    unknown => return Err(format!("Unknown option for history: '{}'", unknown)),
+                }
+            }
+            Ok(Command::History(user, json_output, since, until))
+        },
+
+        "show" => {
+            let user = try!(positional(rest, 0, "USER"));
+            let uuid = try!(parse_uuid(try!(positional(rest, 1, "UUID")).as_str()));
+            let mut delta_only = false;
+            for option in rest.iter().skip(2) {
+                match option.as_str() {
+                    "--delta" => delta_only = true,
+                    unknown => return Err(format!("Unknown option for show: '{}'", unknown)),
+                }
+            }
+            Ok(Command::Show(user, uuid, delta_only))
+        },
+
+        "diff" => {
+            let user = try!(positional(rest, 0, "USER"));
+            let uuid1 = try!(parse_uuid(try!(positional(rest, 1, "UUID1")).as_str()));
+            let uuid2 = try!(parse_uuid(try!(positional(rest, 2, "UUID2")).as_str()));
+            match rest.get(3) {
+                Some(unknown) => Err(format!("Unknown option for diff: '{}'", unknown)),
+                None => Ok(Command::Diff(user, uuid1, uuid2)),
+            }
+        },
+
+        "verify" => {
+            match rest.iter().find(|e| e.starts_with("-")) {
+                Some(unknown) => Err(format!("Unknown option for verify: '{}'", unknown)),
+                None => Ok(Command::Verify(rest.to_vec())),
+            }
+        },
+
+        "query" => {
+            let mut sql = None;
+            let mut rebuild = false;
+            for option in rest.iter() {
+                match option.as_str() {
+                    "--rebuild" => rebuild = true,
+                    unknown if unknown.starts_with("-") => return Err(format!("Unknown option for query: '{}'", unknown)),
+                    query if sql.is_none() => sql = Some(query.to_string()),
+                    unknown => return Err(format!("Unexpected argument for query: '{}'. Quote whole SQL query", unknown)),
+                }
+            }
+            if sql.is_none() && !rebuild {
+                return Err(String::from("Missing argument: SQL"))
+            }
+            Ok(Command::Query(sql, rebuild))
+        },
+
+        "reindex" => {
+            match rest.iter().find(|e| e.starts_with("-")) {
+                Some(unknown) => Err(format!("Unknown option for reindex: '{}'", unknown)),
+                None => Ok(Command::Reindex(rest.to_vec())),
+            }
+        },
+
+        "threats" => {
+            match rest.iter().find(|e| e.starts_with("-")) {
+                Some(unknown) => Err(format!("Unknown option for threats: '{}'", unknown)),
+                None => Ok(Command::Threats(rest.to_vec())),
+            }
+        },
+
+        "domains" => {
+            let mut app_type = None;
+            let mut outdated_only = false;
+            let mut users = vec!();
+            let mut options = rest.iter();
+            while let Some(option) = options.next() {
+                match option.as_str() {
+                    "--type" | "-t" => {
+                        match options.next() {
+                            Some(name) => match webapp_type_of(name) {
+                                Some(known) => app_type = Some(known),
+                                None => return Err(format!("Unknown web app type: '{}'", name)),
+                            },
+                            None => return Err(String::from("Missing value for option: --type")),
+                        }
+                    },
+                    "--outdated" => outdated_only = true,
+                    unknown if unknown.starts_with("-") => return Err(format!("Unknown option for domains: '{}'", unknown)),
+                    user => users.push(user.to_string()),
+                }
+            }
+            Ok(Command::Domains(app_type, outdated_only, users))
+        },
+
+        "spice" => {
+            let type_name = try!(positional(rest, 0, "TYPE"));
+            let app_type = match webapp_type_of(&type_name) {
+                Some(known) => known,
+                None => return Err(format!("Unknown web app type: '{}'", type_name)),
+            };
+            let version = try!(positional(rest, 1, "VERSION"));
+            let release_dir = try!(positional(rest, 2, "DIR"));
+            let mut interpreter = String::new();
+            let mut supported = true;
+            let mut options = rest.iter().skip(3);
+            while let Some(option) = options.next() {
+                match option.as_str() {
+                    "--interpreter" | "-i" => {
+                        match options.next() {
+                            Some(value) => interpreter = value.clone(),
+                            None => return Err(String::from("Missing value for option: --interpreter")),
+                        }
+                    },
+                    "--unsupported" => supported = false,
+                    unknown => return Err(format!("Unknown option for spice: '{}'", unknown)),
+                }
+            }
+            Ok(Command::Spice(app_type, version, release_dir, interpreter, supported))
+        },
+
+        "state" => {
+            let user = try!(positional(rest, 0, "USER"));
+            let domain = try!(positional(rest, 1, "DOMAIN"));
+            match rest.get(2) {
+                Some(unknown) => Err(format!("Unknown option for state: '{}'", unknown)),
+                None => Ok(Command::State(user, domain)),
+            }
+        },
+
+        "prune" => {
+            match rest.iter().find(|e| e.starts_with("-")) {
+                Some(unknown) => Err(format!("Unknown option for prune: '{}'", unknown)),
+                None => Ok(Command::Prune(rest.to_vec())),
+            }
+        },
+
+        "gc" => {
+            match rest.get(0) {
+                Some(unknown) => Err(format!("Unknown option for gc: '{}'", unknown)),
+                None => Ok(Command::Gc),
+            }
+        },
+
+        "help" | "--help" | "-h" => Ok(Command::Help),
+
+        unknown => Err(format!("Unknown command: '{}'", unknown)),
+    }
+}
+
+
+/* summaries are read from index, only json output decodes changesets */
+pub fn history_command(user_name: String, json_output: bool, since: Option<u64>, until: Option<u64>) -> i32 {
+    let (since, until) = (since.unwrap_or(0), until.unwrap_or(u64::max_value()));
+    let store = SpiceStore::local();
+    let index: Vec<IndexEntry> = match store.index(&user_name) {
+        Ok(index) => index.into_iter().filter(|entry| entry.timestamp >= since && entry.timestamp <= until).collect(),
+        Err(err) => {
+            error!("{}", err);
+            return EXIT_FAILURE
+        },
+    };
+    if index.is_empty() {
+        warn!("No changesets found for user: {}", user_name);
+        return EXIT_NOT_FOUND
+    }
+    if json_output {
+        for changeset in store.changesets_between(&user_name, since, until) {
+            println!("{}", changeset);
+        }
+        return EXIT_OK
+    }
+    for entry in index {
+        let kind = if entry.full { "full" } else { "delta" };
+        println!("{} parent: {} timestamp: {} {} domains: {} files: {} removed: {} bytes: {}",
+            entry.uuid, entry.parent, entry.timestamp, kind, entry.domains, entry.files, entry.removed, entry.bytes);
+    }
+    EXIT_OK
+}
+
+
+pub fn show_command(user_name: String, uuid: Uuid, delta_only: bool) -> i32 {
+    let changeset = if delta_only {
+        find_changeset(user_name.clone(), uuid)
+    } else {
+        state_at(&SpiceStore::local(), &user_name, uuid)
+    };
+    match changeset {
+        Some(changeset) => {
+            println!("{}", changeset);
+            EXIT_OK
+        },
+        None => {
+            error!("No changeset: {} found for user: {}", uuid, user_name);
+            EXIT_NOT_FOUND
+        },
+    }
+}
+
+
+pub fn diff_command(user_name: String, uuid1: Uuid, uuid2: Uuid) -> i32 {
+    let store = SpiceStore::local();
+    let (a, b) = (state_at(&store, &user_name, uuid1), state_at(&store, &user_name, uuid2));
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            print_difference(
+                calculate_difference(
+                    local_content_of(&a),
+                    local_content_of(&b),
+                    ""));
+            EXIT_OK
+        },
+        (None, _) => {
+            error!("No changeset: {} found for user: {}", uuid1, user_name);
+            EXIT_NOT_FOUND
+        },
+        (_, None) => {
+            error!("No changeset: {} found for user: {}", uuid2, user_name);
+            EXIT_NOT_FOUND
+        },
+    }
+}
+
+
+pub fn state_command(user_name: String, domain: String) -> i32 {
+    match SpiceStore::local().domain_state(&user_name, &domain) {
+        Some(state) => {
+            println!("{}", state);
+            EXIT_OK
+        },
+        None => {
+            error!("No state of domain: {} found for user: {}", domain, user_name);
+            EXIT_NOT_FOUND
+        },
+    }
+}
+
+
+/* current domains of user, from their state documents (or replayed history, if there are none yet) */
+fn current_domains(user_name: &str) -> Vec<DomainEntry> {
+    let states = SpiceStore::local().domain_states(user_name);
+    if states.is_empty() {
+        return current_state(user_name.to_string()).entries
+    }
+    states.into_iter().map(|state| state.domain).collect()
+}
+
+
+pub fn verify_command(users: Vec<String>) -> i32 {
+    let user_names = if users.is_empty() {
+        changeset_users()
+    } else {
+        users
+    };
+    let mut corrupted = 0;
+    let mut unreadable = 0;
+    for user_name in user_names {
+        let changesets = match all_changesets(user_name.clone()) {
+            Ok(changesets) => changesets,
+            Err(err) => {
+                error!("User: {}. {}", user_name, err);
+                unreadable += 1;
+                continue
+            },
+        };
+        let quarantined = quarantined_changesets(&user_name);
+        for file_name in quarantined.iter() {
+            error!("User: {} has corrupted changeset: {}", user_name, file_name);
+        }
+        /* undecodable changesets and deltas which parent is gone */
+        let invalid = quarantined.len() + changesets
+            .iter()
+            .filter(|e| !e.full && !changesets.iter().any(|parent| parent.uuid == e.parent))
+            .count();
+        if invalid > 0 {
+            error!("User: {} has {} corrupted changesets (of {})", user_name, invalid, changesets.len() + quarantined.len());
+        } else {
+            info!("User: {} has {} valid changesets", user_name, changesets.len());
+        }
+        corrupted += invalid;
+    }
+    if corrupted > 0 {
+        EXIT_CORRUPTED
+    } else if unreadable > 0 {
+        EXIT_FAILURE
+    } else {
+        EXIT_OK
+    }
+}
+
+
+pub fn domains_command(app_type: Option<WebAppTypes>, outdated_only: bool, users: Vec<String>) -> i32 {
+    let user_names = if users.is_empty() {
+        changeset_users()
+    } else {
+        users
+    };
+    for user_name in user_names {
+        for entry in current_domains(&user_name) {
+            if app_type.is_some() && entry.app_type != app_type {
+                continue
+            }
+            if outdated_only && !entry.outdated {
+                continue
+            }
+            let type_name = match entry.app_type {
+                Some(ref detected) => format!("{:?}", detected),
+                None => String::from("-"),
+            };
+            let status = if entry.zombie {
+                "zombie"
+            } else if entry.outdated {
+                "outdated"
+            } else {
+                "-"
+            };
+            let version = if entry.version.is_empty() { "-" } else { entry.version.as_str() };
+            println!("{} {} {} {} {} {:?}", user_name, entry.name, type_name, version, status, verdict_of(&entry.states));
+        }
+    }
+    EXIT_OK
+}
+
+
+/* prints: user domain path suspicion signatures, of files from current state of users */
+pub fn threats_command(users: Vec<String>) -> i32 {
+    let user_names = if users.is_empty() {
+        changeset_users()
+    } else {
+        users
+    };
+    let threshold = config().suspicion_threshold;
+    for user_name in user_names {
+        for entry in current_domains(&user_name) {
+            for file in entry.files.iter().filter(|file| is_suspicious(file, threshold)) {
+                let signatures = file.signature_hits.iter().map(|hit| hit.name.clone()).collect::<Vec<String>>().join(",");
+                let signatures = if signatures.is_empty() { String::from("-") } else { signatures };
+                println!("{} {} {} {} {}", user_name, entry.name, file.path, file.suspicion, signatures);
+            }
+        }
+    }
+    EXIT_OK
+}
+
+
+pub fn prune_command(users: Vec<String>) -> i32 {
+    let user_names = if users.is_empty() {
+        changeset_users()
+    } else {
+        users
+    };
+    let config = config();
+    let now = precise_time_ns() / 1000 / 1000;
+    let mut exit_code = EXIT_OK;
+    for user_name in user_names {
+        let changesets = match all_changesets(user_name.clone()) {
+            Ok(changesets) => changesets,
+            Err(err) => {
+                error!("User: {}. {}", user_name, err);
+                exit_code = EXIT_FAILURE;
+                continue
+            },
+        };
+        let keep = retained(&changesets, config.keep_last, config.keep_daily, config.keep_weekly, now);
+        let (rewritten, removed) = match prune_chain(&changesets, &keep) {
+            Ok(result) => result,
+            Err(err) => {
+                error!("User: {}. {}", user_name, err);
+                exit_code = EXIT_CORRUPTED;
+                continue
+            },
+        };
+        /* successors are stored before their parents are removed, so interrupted prune leaves consistent chain */
+        let mut stored = true;
+        for changeset in rewritten {
+            let uuid = changeset.uuid;
+            match store_changeset(user_name.clone(), changeset) {
+                Ok((_, bytes_written)) => debug!("Changeset squashed: {} ({} bytes)", uuid, bytes_written),
+                Err(err) => {
+                    error!("{}", err);
+                    stored = false;
+                },
+            }
+        }
+        if !stored {
+            error!("User: {}. Changesets weren't removed, since squashed successors weren't stored", user_name);
+            exit_code = EXIT_FAILURE;
+            continue
+        }
+        for changeset in removed.iter() {
+            match remove_changeset(&user_name, changeset) {
+                Ok(_) => debug!("Changeset pruned: {}", changeset.uuid),
+                Err(err) => {
+                    error!("{}", err);
+                    exit_code = EXIT_FAILURE;
+                },
+            }
+        }
+        info!("User: {} pruned: {} changesets, kept: {}", user_name, removed.len(), keep.len());
+    }
+    info!("Run gc to remove contents of files no longer referenced");
+    exit_code
+}
+
+
+pub fn reindex_command(users: Vec<String>) -> i32 {
+    let store = SpiceStore::local();
+    let users = if users.is_empty() { store.users() } else { users };
+    let mut exit_code = EXIT_OK;
+    for user_name in users {
+        match store.rebuild_index(&user_name) {
+            Ok(index) => info!("User: {}. Indexed: {} changesets", user_name, index.len()),
+            Err(err) => {
+                error!("User: {}. {}", user_name, err);
+                exit_code = EXIT_FAILURE;
+            },
+        }
+    }
+    exit_code
+}
+
+
+pub fn query_command(sql: Option<String>, rebuild: bool) -> i32 {
+    if rebuild {
+        let states = changeset_users().into_iter().map(|user_name| (user_name.clone(), current_state(user_name))).collect();
+        match rebuild_query_store(states) {
+            Ok(users) => info!("Query store rebuilt from current state of: {} users", users),
+            Err(err) => {
+                error!("{}", err);
+                return EXIT_FAILURE
+            },
+        }
+    }
+    let sql = match sql {
+        Some(sql) => sql,
+        None => return EXIT_OK,
+    };
+    match run_query(&sql) {
+        Ok((columns, rows)) => {
+            println!("{}", columns.join("\t"));
+            for row in rows {
+                println!("{}", row.join("\t"));
+            }
+            EXIT_OK
+        },
+        Err(err) => {
+            error!("{}", err);
+            EXIT_FAILURE
+        },
+    }
+}
+
+
+/*
+    blobs dir may be shared by all users and hosts under store dir, so changesets of each of them are counted.
+    Blobs referenced only by hosts with other store dir must not be kept in the same blobs dir.
+    References are counted user by user, so only changesets of one user are held in memory at once.
+ */
+pub fn gc_command() -> i32 {
+    let mut references: HashMap<String, usize> = HashMap::new();
+    for store in SpiceStore::all_hosts() {
+        for user_name in store.users() {
+            let quarantined = store.quarantined_changesets(&user_name);
+            if !quarantined.is_empty() {
+                warn!("{} corrupted changesets of user: {} on host: {} in quarantine: {}. Blobs referenced only by them aren't kept",
+                    quarantined.len(), user_name, store.host, store.quarantine_dir(&user_name));
+            }
+            match store.changesets(&user_name) {
+                Ok(stored) => {
+                    for (sha256, count) in blob_references(&stored) {
+                        *references.entry(sha256).or_insert(0) += count;
+                    }
+                },
+                Err(err) => {
+                    error!("User: {} on host: {}. {}. Nothing was removed", user_name, store.host, err);
+                    return EXIT_FAILURE
+                },
+            }
+        }
+    }
+    match collect_garbage(&config().blobs_dir, &references, BLOB_GRACE_PERIOD) {
+        Ok((removed, kept)) => {
+            info!("Removed: {} unreferenced blobs, kept: {} blobs", removed, kept);
+            EXIT_OK
+        },
+        Err(err) => {
+            error!("{}", err);
+            EXIT_FAILURE
+        },
+    }
+}
+
+
+pub fn spice_command(app_type: WebAppTypes, version: String, release_dir: String, interpreter: String, supported: bool) -> i32 {
+    let baseline = match gather_baseline(&release_dir, app_type, &version, &interpreter, supported) {
+        Ok(baseline) => baseline,
+        Err(err) => {
+            error!("{}", err);
+            return EXIT_FAILURE
+        },
+    };
+    match store_baseline(&config().spice_dir, &baseline) {
+        Ok(file_name) => {
+            info!("Baseline of: {:?} {} ({} checksums) stored in: {}", baseline.app_type, baseline.version, baseline.checksums.len(), file_name);
+            EXIT_OK
+        },
+        Err(err) => {
+            error!("{}", err);
+            EXIT_FAILURE
+        },
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn parse_arguments_test() {
+    let args = |list: Vec<&str>| list.into_iter().map(|e| e.to_string()).collect::<Vec<String>>();
+    let uuid = root_uuid().to_string();
+
+    assert!(parse_arguments(&args(vec!())) == Ok(Command::Scan(vec!(), None, false)));
+    assert!(parse_arguments(&args(vec!("scan", "--user", "admin6"))) == Ok(Command::Scan(vec!(String::from("admin6")), None, false)));
+    assert!(parse_arguments(&args(vec!("scan", "--severity", "psycho", "--full"))) == Ok(Command::Scan(vec!(), Some(SeverityLevels::Psycho), true)));
+    assert!(parse_arguments(&args(vec!("s"))) == Ok(Command::Serve));
+    assert!(parse_arguments(&args(vec!("history", "admin6", "--json"))) == Ok(Command::History(String::from("admin6"), true, None, None)));
+    assert!(parse_arguments(&args(vec!("history", "admin6", "--since", "1000", "--until", "2000"))) == Ok(Command::History(String::from("admin6"), false, Some(1000), Some(2000))));
+    assert!(parse_arguments(&args(vec!("reindex"))) == Ok(Command::Reindex(vec!())));
+    assert!(parse_arguments(&args(vec!("query", "SELECT * FROM domains"))) == Ok(Command::Query(Some(String::from("SELECT * FROM domains")), false)));
+    assert!(parse_arguments(&args(vec!("query", "--rebuild"))) == Ok(Command::Query(None, true)));
+    assert!(parse_arguments(&args(vec!("show", "admin6", uuid.as_str()))) == Ok(Command::Show(String::from("admin6"), root_uuid(), false)));
+    assert!(parse_arguments(&args(vec!("show", "admin6", uuid.as_str(), "--delta"))) == Ok(Command::Show(String::from("admin6"), root_uuid(), true)));
+    assert!(parse_arguments(&args(vec!("diff", "admin6", uuid.as_str(), uuid.as_str()))) == Ok(Command::Diff(String::from("admin6"), root_uuid(), root_uuid())));
+    assert!(parse_arguments(&args(vec!("verify", "admin6", "admin7"))) == Ok(Command::Verify(args(vec!("admin6", "admin7")))));
+    assert!(parse_arguments(&args(vec!("threats"))) == Ok(Command::Threats(vec!())));
+    assert!(parse_arguments(&args(vec!("gc"))) == Ok(Command::Gc));
+    assert!(parse_arguments(&args(vec!("state", "admin6", "example.com"))) == Ok(Command::State(String::from("admin6"), String::from("example.com"))));
+    assert!(parse_arguments(&args(vec!("prune", "admin6"))) == Ok(Command::Prune(args(vec!("admin6")))));
+    assert!(parse_arguments(&args(vec!("domains", "--type", "wordpress", "admin6"))) == Ok(Command::Domains(Some(WebAppTypes::WordPress), false, args(vec!("admin6")))));
+    assert!(parse_arguments(&args(vec!("domains", "--outdated"))) == Ok(Command::Domains(None, true, vec!())));
+    assert!(parse_arguments(&args(vec!("spice", "wordpress", "4.5.2", "/tmp/wordpress", "--interpreter", "5.2"))) ==
+        Ok(Command::Spice(WebAppTypes::WordPress, String::from("4.5.2"), String::from("/tmp/wordpress"), String::from("5.2"), true)));
+
+    for invalid in vec!(
+        vec!("scan", "--user"), vec!("scan", "--severity", "paranoid"), vec!("history"), vec!("history", "admin6", "--since", "yesterday"), vec!("history", "admin6", "--until"), vec!("show", "admin6"), vec!("show", "admin6", "not-uuid"),
+        vec!("diff", "admin6", uuid.as_str()), vec!("verify", "--all"), vec!("threats", "--all"), vec!("gc", "admin6"), vec!("query"), vec!("query", "SELECT", "*"), vec!("state", "admin6"), vec!("prune", "--all"), vec!("domains", "--type", "typo3"),
+        vec!("spice", "wordpress", "4.5.2"), vec!("spice", "typo3", "8.0", "/tmp/typo3"), vec!("unknown")
+    ) {
+        assert!(parse_arguments(&args(invalid.clone())).is_err(), format!("Expected error for: {:?}", invalid));
+    }
+
+    let mut with_config = args(vec!("--config", "/etc/yak-s1.json", "history", "admin6"));
+    assert!(extract_config_option(&mut with_config) == Ok(Some(String::from("/etc/yak-s1.json"))));
+    assert!(with_config == args(vec!("history", "admin6")));
+    assert!(extract_config_option(&mut args(vec!("scan", "--config"))).is_err());
+}
@@ -0,0 +1,358 @@
+use process::*;
+use states::severity_level_of;
+
+use std::sync::RwLock;
+use std::str::FromStr;
+use std::collections::BTreeMap;
+use std::process::Command;
+use rustc_serialize::json::Json;
+
+
+/* default location of config file, used when no --config nor YAK_CONFIG given */
+pub fn default_config_file() -> String {
+    String::from("/etc/yak.json")
+}
+
+
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct Config {
+    pub home_dir: String, /* "{user}" is replaced with user name */
+    pub layout: String, /* hosting layout: directadmin, cpanel, plesk or custom */
+    pub layout_pattern: String, /* path pattern of custom layout, f.e.: "/srv/www/{user}/{domain}/htdocs" */
+    pub cpanel_userdata_dir: String,
+    pub plesk_vhosts_dir: String,
+    pub max_depth: usize, /* traverse depth below user domains dir, document root being: {domain}/public_html */
+    pub read_limit: u64,
+    pub http_port: u16,
+    pub connection_timeout: usize,
+    pub timeout: usize,
+    pub store_dir: String, /* root of SpiceStore with changesets of each host and user */
+    pub changesets_dir: String, /* changesets of first releases: CHANGESETS_DIR/USER_NAME/*.chgset, imported to SpiceStore by scan */
+    pub hostname: String, /* name of this host in SpiceStore, detected if empty */
+    pub query_db: String, /* SQLite database with current scan results of all users, used by query command. Default: STORE_DIR/query.db */
+    pub blobs_dir: String, /* compressed file contents, shared by changesets of all users. Default: STORE_DIR/blobs */
+    pub keep_last: usize, /* retention of changesets: number of most recent ones */
+    pub keep_daily: usize, /* days with newest changeset of each day kept */
+    pub keep_weekly: usize, /* weeks with newest changeset of each week kept */
+    pub releases_file: String, /* JSON table of latest and supported web app releases. Default: STORE_DIR/releases.json */
+    pub spice_dir: String, /* pristine checksum baselines of web apps. Default: STORE_DIR/spice */
+    pub signatures_dir: String, /* JSON rule files of malware signature engine. Default: STORE_DIR/signatures */
+    pub suspicion_threshold: u32, /* files with heuristic suspicion score (0-100) above are reported */
+    pub severity: SeverityLevels, /* normal, pedantic or psycho */
+    pub probe_paths: Vec<String>, /* paths probed on each domain, besides its index, f.e.: "wp-login.php" */
+}
+
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            home_dir: String::from("/home/{user}/"),
+            layout: String::from("directadmin"),
+            layout_pattern: String::new(),
+            cpanel_userdata_dir: String::from("/var/cpanel/userdata"),
+            plesk_vhosts_dir: String::from("/var/www/vhosts"),
+            max_depth: 4,
+            read_limit: 131070, /* 128KiB */
+            http_port: root_default_http_port(),
+            connection_timeout: root_default_connection_timeout(),
+            timeout: root_default_timeout(),
+            store_dir: String::from("/Spice"),
+            changesets_dir: String::from(".changesets"),
+            hostname: String::new(),
+            query_db: String::new(),
+            blobs_dir: String::new(),
+            keep_last: 10,
+            keep_daily: 7,
+            keep_weekly: 4,
+            releases_file: String::new(),
+            spice_dir: String::new(),
+            signatures_dir: String::new(),
+            suspicion_threshold: 50,
+            severity: SeverityLevels::Normal,
+            probe_paths: vec!(),
+        }
+    }
+}
+
+
+impl Config {
+    pub fn home_dir_of(&self, user_name: &str) -> String {
+        self.home_dir.replace("{user}", user_name)
+    }
+
+    /* paths not given explicitly are placed under store dir, so scans from cron and API server use the same ones, whatever their working dir is */
+    pub fn resolved(mut self) -> Config {
+        let store_dir = self.store_dir.trim_right_matches('/').to_string();
+        let under_store_dir = |path: &mut String, name: &str| {
+            if path.is_empty() {
+                *path = format!("{}/{}", store_dir, name);
+            }
+        };
+        under_store_dir(&mut self.query_db, "query.db");
+        under_store_dir(&mut self.blobs_dir, "blobs");
+        under_store_dir(&mut self.releases_file, "releases.json");
+        under_store_dir(&mut self.spice_dir, "spice");
+        under_store_dir(&mut self.signatures_dir, "signatures");
+        self
+    }
+
+    /* configured host name, or name of this system */
+    pub fn host_name(&self) -> String {
+        if !self.hostname.is_empty() {
+            return self.hostname.clone()
+        }
+        match Command::new("hostname").output() {
+            Ok(ref output) if output.status.success() => {
+                let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                if name.is_empty() { String::from("localhost") } else { name }
+            },
+            _ => String::from("localhost"),
+        }
+    }
+}
+
+
+lazy_static! {
+    static ref CONFIG: RwLock<Config> = RwLock::new(Config::default().resolved());
+}
+
+
+/* returns copy of currently used configuration */
+pub fn config() -> Config {
+    match CONFIG.read() {
+        Ok(config) => config.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+
+pub fn set_config(new_config: Config) {
+    match CONFIG.write() {
+        Ok(mut config) => *config = new_config,
+        Err(poisoned) => *poisoned.into_inner() = new_config,
+    }
+}
+
+
+fn string_value(object: &BTreeMap<String, Json>, key: &str) -> Result<Option<String>, String> {
+    match object.get(key) {
+        Some(&Json::String(ref value)) => Ok(Some(value.clone())),
+        Some(other) => Err(format!("Config value of: '{}' should be a string, got: {}", key, other)),
+        None => Ok(None),
+    }
+}
+
+
+fn string_list_value(object: &BTreeMap<String, Json>, key: &str) -> Result<Option<Vec<String>>, String> {
+    match object.get(key) {
+        Some(&Json::Array(ref values)) => {
+            let mut list = vec!();
+            for value in values {
+                match *value {
+                    Json::String(ref value) => list.push(value.clone()),
+                    ref other => return Err(format!("Config value of: '{}' should be a list of strings, got: {}", key, other)),
+                }
+            }
+            Ok(Some(list))
+        },
+        Some(other) => Err(format!("Config value of: '{}' should be a list of strings, got: {}", key, other)),
+        None => Ok(None),
+    }
+}
+
+
+fn number_value(object: &BTreeMap<String, Json>, key: &str) -> Result<Option<u64>, String> {
+    match object.get(key) {
+        Some(value) => match value.as_u64() {
+            Some(number) => Ok(Some(number)),
+            None => Err(format!("Config value of: '{}' should be a positive number, got: {}", key, value)),
+        },
+        None => Ok(None),
+    }
+}
+
+
+/* parses JSON config, every value is optional and falls back to default */
+pub fn parse_config(content: &str) -> Result<Config, String> {
+    let json = match Json::from_str(content) {
+        Ok(json) => json,
+        Err(err) => return Err(format!("Config parse failure: {}", err)),
+    };
+    let object = match json.as_object() {
+        Some(object) => object.clone(),
+        None => return Err(String::from("Config should be a JSON object")),
+    };
+    let mut config = Config::default();
+    for key in object.keys() {
+        match key.as_str() {
+            "home_dir" | "layout" | "layout_pattern" | "cpanel_userdata_dir" | "plesk_vhosts_dir" |
+            "max_depth" | "read_limit" | "http_port" |
+            "connection_timeout" | "timeout" | "store_dir" | "hostname" | "changesets_dir" | "blobs_dir" | "releases_file" | "spice_dir" | "signatures_dir" |
+            "suspicion_threshold" | "severity" | "probe_paths" | "keep_last" | "keep_daily" | "keep_weekly" | "query_db" => {},
+            unknown => warn!("Unknown config key: '{}'. Ignored", unknown),
+        }
+    }
+    if let Some(value) = try!(string_value(&object, "home_dir")) { config.home_dir = value }
+    if let Some(value) = try!(string_value(&object, "layout")) { config.layout = value }
+    if let Some(value) = try!(string_value(&object, "layout_pattern")) { config.layout_pattern = value }
+    if let Some(value) = try!(string_value(&object, "cpanel_userdata_dir")) { config.cpanel_userdata_dir = value }
+    if let Some(value) = try!(string_value(&object, "plesk_vhosts_dir")) { config.plesk_vhosts_dir = value }
+    if let Some(value) = try!(number_value(&object, "max_depth")) { config.max_depth = value as usize }
+    if let Some(value) = try!(number_value(&object, "read_limit")) { config.read_limit = value }
+    if let Some(value) = try!(number_value(&object, "http_port")) {
+        if value > u16::max_value() as u64 {
+            return Err(format!("Config value of: 'http_port' should be a port number (up to: {}), got: {}", u16::max_value(), value))
+        }
+        config.http_port = value as u16
+    }
+    if let Some(value) = try!(number_value(&object, "connection_timeout")) { config.connection_timeout = value as usize }
+    if let Some(value) = try!(number_value(&object, "timeout")) { config.timeout = value as usize }
+    if let Some(value) = try!(string_value(&object, "store_dir")) { config.store_dir = value }
+    if let Some(value) = try!(string_value(&object, "changesets_dir")) { config.changesets_dir = value }
+    if let Some(value) = try!(string_value(&object, "hostname")) { config.hostname = value }
+    if let Some(value) = try!(string_value(&object, "query_db")) { config.query_db = value }
+    if let Some(value) = try!(string_value(&object, "blobs_dir")) { config.blobs_dir = value }
+    if let Some(value) = try!(number_value(&object, "keep_last")) { config.keep_last = value as usize }
+    if let Some(value) = try!(number_value(&object, "keep_daily")) { config.keep_daily = value as usize }
+    if let Some(value) = try!(number_value(&object, "keep_weekly")) { config.keep_weekly = value as usize }
+    if let Some(value) = try!(string_value(&object, "releases_file")) { config.releases_file = value }
+    if let Some(value) = try!(string_value(&object, "spice_dir")) { config.spice_dir = value }
+    if let Some(value) = try!(string_value(&object, "signatures_dir")) { config.signatures_dir = value }
+    if let Some(value) = try!(number_value(&object, "suspicion_threshold")) { config.suspicion_threshold = value as u32 }
+    if let Some(value) = try!(string_value(&object, "severity")) { config.severity = try!(parse_severity("severity", &value)) }
+    if let Some(value) = try!(string_list_value(&object, "probe_paths")) { config.probe_paths = value }
+    Ok(config)
+}
+
+
+fn parse_severity(key: &str, value: &str) -> Result<SeverityLevels, String> {
+    match severity_level_of(value) {
+        Some(level) => Ok(level),
+        None => Err(format!("Invalid severity level: '{}' given in: {}. Expected one of: normal, pedantic, psycho", value, key)),
+    }
+}
+
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(number) => Ok(number),
+        Err(_) => Err(format!("Invalid number: '{}' given in: {}", value, key)),
+    }
+}
+
+
+/* YAK_* environment variables take precedence over values read from config file */
+pub fn apply_env_overrides(mut config: Config, vars: Vec<(String, String)>) -> Result<Config, String> {
+    for (key, value) in vars {
+        match key.as_str() {
+            "YAK_HOME_DIR" => config.home_dir = value,
+            "YAK_LAYOUT" => config.layout = value,
+            "YAK_LAYOUT_PATTERN" => config.layout_pattern = value,
+            "YAK_CPANEL_USERDATA_DIR" => config.cpanel_userdata_dir = value,
+            "YAK_PLESK_VHOSTS_DIR" => config.plesk_vhosts_dir = value,
+            "YAK_MAX_DEPTH" => config.max_depth = try!(parse_number(&key, &value)),
+            "YAK_READ_LIMIT" => config.read_limit = try!(parse_number(&key, &value)),
+            "YAK_HTTP_PORT" => config.http_port = try!(parse_number(&key, &value)),
+            "YAK_CONNECTION_TIMEOUT" => config.connection_timeout = try!(parse_number(&key, &value)),
+            "YAK_TIMEOUT" => config.timeout = try!(parse_number(&key, &value)),
+            "YAK_STORE_DIR" => config.store_dir = value,
+            "YAK_CHANGESETS_DIR" => config.changesets_dir = value,
+            "YAK_HOSTNAME" => config.hostname = value,
+            "YAK_QUERY_DB" => config.query_db = value,
+            "YAK_BLOBS_DIR" => config.blobs_dir = value,
+            "YAK_KEEP_LAST" => config.keep_last = try!(parse_number(&key, &value)),
+            "YAK_KEEP_DAILY" => config.keep_daily = try!(parse_number(&key, &value)),
+            "YAK_KEEP_WEEKLY" => config.keep_weekly = try!(parse_number(&key, &value)),
+            "YAK_RELEASES_FILE" => config.releases_file = value,
+            "YAK_SPICE_DIR" => config.spice_dir = value,
+            "YAK_SIGNATURES_DIR" => config.signatures_dir = value,
+            "YAK_SUSPICION_THRESHOLD" => config.suspicion_threshold = try!(parse_number(&key, &value)),
+            "YAK_SEVERITY" => config.severity = try!(parse_severity(&key, &value)),
+            "YAK_PROBE_PATHS" => config.probe_paths = value.split(',').map(|e| e.trim().to_string()).filter(|e| !e.is_empty()).collect(),
+            _ => {},
+        }
+    }
+    Ok(config)
+}
+
+
+/* config_file - path given with --config, then YAK_CONFIG, then default config file (optional) */
+pub fn load_config(config_file: Option<String>) -> Result<Config, String> {
+    let (path, required) = match config_file.or(env::var("YAK_CONFIG").ok()) {
+        Some(path) => (path, true),
+        None => (default_config_file(), false),
+    };
+    let config = match File::open(path.clone()) {
+        Ok(file) => {
+            let mut reader = BufReader::new(file);
+            let mut content = String::new();
+            match reader.read_to_string(&mut content) {
+                Ok(_) => {
+                    info!("Loading config from: {}", path);
+                    try!(parse_config(content.as_str()))
+                },
+                Err(err) => return Err(format!("Failed to read config: {}. Cause: {}", path, err)),
+            }
+        },
+        Err(err) => {
+            if required {
+                return Err(format!("Failed to open config: {}. Cause: {}", path, err))
+            }
+            debug!("No config file: {}. Using defaults", path);
+            Config::default()
+        },
+    };
+    let mut config = try!(apply_env_overrides(config, env::vars().collect())).resolved();
+    config.hostname = config.host_name(); /* detected once */
+    Ok(config)
+}
+
+
+#[cfg(test)]
+#[test]
+fn parse_config_test() {
+    assert!(parse_config("{}") == Ok(Config::default()));
+
+    let config = parse_config(r#"{"home_dir": "/usr/home/{user}/", "layout": "cpanel", "max_depth": 6, "http_port": 3005, "store_dir": "/var/yak"}"#).unwrap();
+    assert!(config.home_dir_of("admin6") == "/usr/home/admin6/");
+    assert!(config.layout == "cpanel");
+    assert!(config.max_depth == 6);
+    assert!(config.http_port == 3005);
+    assert!(config.store_dir == "/var/yak");
+    let legacy = parse_config(r#"{"changesets_dir": "/var/yak-old"}"#).unwrap();
+    assert!(legacy.changesets_dir == "/var/yak-old" && legacy.store_dir == Config::default().store_dir);
+    assert!(parse_config(r#"{"hostname": "s1"}"#).unwrap().host_name() == "s1");
+    assert!(parse_config(r#"{"query_db": "/var/yak/query.db"}"#).unwrap().query_db == "/var/yak/query.db");
+    assert!(!Config::default().host_name().is_empty());
+    assert!(config.read_limit == Config::default().read_limit);
+
+    assert!(parse_config(r#"{"blobs_dir": "/var/yak-blobs"}"#).unwrap().blobs_dir == "/var/yak-blobs");
+    assert!(parse_config(r#"{"keep_last": 3, "keep_weekly": 0}"#).unwrap().keep_weekly == 0);
+    assert!(parse_config(r#"{"severity": "Pedantic"}"#).unwrap().severity == SeverityLevels::Pedantic);
+    assert!(parse_config(r#"{"probe_paths": ["wp-login.php", "/robots.txt"]}"#).unwrap().probe_paths == vec!(String::from("wp-login.php"), String::from("/robots.txt")));
+    for invalid in vec!("[]", "{", r#"{"max_depth": "6"}"#, r#"{"severity": "paranoid"}"#, r#"{"probe_paths": "wp-login.php"}"#, r#"{"probe_paths": [1]}"#, r#"{"home_dir": 1}"#, r#"{"timeout": -1}"#, r#"{"http_port": 70000}"#) {
+        assert!(parse_config(invalid).is_err(), invalid);
+    }
+
+    let vars = vec!(
+        (String::from("YAK_HTTP_PORT"), String::from("3001")),
+        (String::from("YAK_STORE_DIR"), String::from("/tmp/spice")),
+        (String::from("YAK_PROBE_PATHS"), String::from("wp-login.php, xmlrpc.php,")),
+        (String::from("YAK_KEEP_DAILY"), String::from("14")),
+        (String::from("YAK_PLESK_VHOSTS_DIR"), String::from("/srv/vhosts")),
+        (String::from("HOME"), String::from("/root")),
+    );
+    let overriden = apply_env_overrides(config, vars).unwrap();
+    assert!(overriden.http_port == 3001);
+    assert!(overriden.store_dir == "/tmp/spice");
+    assert!(overriden.probe_paths == vec!(String::from("wp-login.php"), String::from("xmlrpc.php")));
+    assert!(overriden.keep_daily == 14);
+    assert!(overriden.plesk_vhosts_dir == "/srv/vhosts");
+    let resolved = overriden.resolved();
+    assert!(resolved.blobs_dir == "/tmp/spice/blobs" && resolved.signatures_dir == "/tmp/spice/signatures");
+    assert!(resolved.query_db == "/tmp/spice/query.db");
+    assert!(parse_config(r#"{"spice_dir": "/opt/spice"}"#).unwrap().resolved().spice_dir == "/opt/spice");
+    assert!(apply_env_overrides(Config::default(), vec!((String::from("YAK_HTTP_PORT"), String::from("70000")))).is_err());
+    assert!(overriden.max_depth == 6);
+    assert!(apply_env_overrides(Config::default(), vec!((String::from("YAK_TIMEOUT"), String::from("soon")))).is_err());
+}
@@ -0,0 +1,250 @@
+use process::*;
+
+use std::collections::HashSet;
+
+
+/* compared by content and metadata only, age of file ("modified") changes with each run */
+fn same_file(a: &FileEntry, b: &FileEntry) -> bool {
+    a.path == b.path && a.raw_sha256 == b.raw_sha256 && a.size == b.size && a.mode == b.mode &&
+        a.mtime == b.mtime && a.inode == b.inode && a.owner.uid == b.owner.uid && a.owner.gid == b.owner.gid &&
+        a.signature_hits == b.signature_hits && a.suspicion == b.suspicion
+}
+
+
+/* compares domain attributes, without files and response times */
+fn same_domain(a: &DomainEntry, b: &DomainEntry) -> bool {
+    a.docroot == b.docroot && a.app_type == b.app_type && a.version == b.version && a.interpreter == b.interpreter &&
+        a.environment == b.environment && a.outdated == b.outdated && a.zombie == b.zombie &&
+        a.fs_diffs == b.fs_diffs && a.fs_suspicious == b.fs_suspicious && a.encodings == b.encodings && a.states == b.states &&
+        a.http_status_code == b.http_status_code && a.http_content_size == b.http_content_size &&
+        a.https_status_code == b.https_status_code && a.https_content_size == b.https_content_size &&
+        a.probes.len() == b.probes.len() &&
+        a.probes.iter().zip(b.probes.iter()).all(|(x, y)| x.url == y.url && x.status_code == y.status_code && x.content_size == y.content_size)
+}
+
+
+/* changeset with domains and files added, modified and removed since previous state of user */
+pub fn delta_of(previous: &Changeset, current: Changeset) -> Changeset {
+    let (uuid, timestamp, severity) = (current.uuid, current.timestamp, current.severity.clone());
+    let current_names: HashSet<String> = current.entries.iter().map(|domain| domain.name.clone()).collect();
+    let removed: Vec<String> = previous.entries
+        .iter()
+        .filter(|domain| !current_names.contains(&domain.name))
+        .map(|domain| domain.name.clone())
+        .collect();
+
+    let mut entries = vec!();
+    for domain in current.entries {
+        let delta = match previous.entries.iter().find(|previous_domain| previous_domain.name == domain.name) {
+            Some(previous_domain) => {
+                let previous_files: HashMap<&str, &FileEntry> = previous_domain.files.iter().map(|file| (file.path.as_str(), file)).collect();
+                let current_paths: HashSet<&str> = domain.files.iter().map(|file| file.path.as_str()).collect();
+                let changed_files: Vec<FileEntry> = domain.files
+                    .iter()
+                    .filter(|file| match previous_files.get(file.path.as_str()) {
+                        Some(previous_file) => !same_file(previous_file, file),
+                        None => true,
+                    })
+                    .cloned()
+                    .collect();
+                let removed_files: Vec<String> = previous_domain.files
+                    .iter()
+                    .filter(|file| !current_paths.contains(file.path.as_str()))
+                    .map(|file| file.path.clone())
+                    .collect();
+                if changed_files.is_empty() && removed_files.is_empty() && same_domain(previous_domain, &domain) {
+                    None
+                } else {
+                    Some((changed_files, removed_files))
+                }
+            },
+            None => Some((domain.files.clone(), vec!())), /* new domain */
+        };
+        match delta {
+            Some((files, removed_files)) => entries.push(DomainEntry { files: files, removed_files: removed_files, .. domain }),
+            None => {},
+        }
+    }
+    Changeset {
+        uuid: uuid,
+        parent: previous.uuid,
+        timestamp: timestamp,
+        severity: severity,
+        full: false,
+        removed: removed,
+        entries: entries,
+    }
+}
+
+
+/* state after applying given changeset on top of given state */
+pub fn apply_delta(mut state: Changeset, delta: &Changeset) -> Changeset {
+    if delta.full {
+        return delta.clone()
+    }
+    state.entries.retain(|domain| !delta.removed.contains(&domain.name));
+    for domain in delta.entries.iter() {
+        let replaced: HashSet<&str> = domain.files
+            .iter()
+            .map(|file| file.path.as_str())
+            .chain(domain.removed_files.iter().map(|path| path.as_str()))
+            .collect();
+        let position = state.entries.iter().position(|previous| previous.name == domain.name);
+        let mut files: Vec<FileEntry> = match position {
+            Some(index) => state.entries.remove(index).files.into_iter().filter(|file| !replaced.contains(file.path.as_str())).collect(),
+            None => vec!(),
+        };
+        files.extend(domain.files.iter().cloned());
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        state.entries.push(DomainEntry { files: files, removed_files: vec!(), .. domain.clone() });
+    }
+    state.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Changeset {
+        uuid: delta.uuid,
+        parent: delta.parent,
+        timestamp: delta.timestamp,
+        severity: delta.severity.clone(),
+        full: true,
+        removed: vec!(),
+        entries: state.entries,
+    }
+}
+
+
+/* full state at given changeset, built by replaying chain of changesets from last full snapshot */
+pub fn materialize(changesets: &[Changeset], uuid: Uuid) -> Option<Changeset> {
+    let mut chain = vec!();
+    let mut next = uuid;
+    loop {
+        match changesets.iter().find(|changeset| changeset.uuid == next) {
+            Some(changeset) => {
+                chain.push(changeset);
+                if changeset.full || changeset.parent == root_uuid() {
+                    break
+                }
+                if chain.len() > changesets.len() {
+                    error!("Cycle in chain of changesets found at: {}", changeset.uuid);
+                    return None
+                }
+                next = changeset.parent;
+            },
+            None => {
+                if !chain.is_empty() {
+                    error!("Broken chain of changesets. Missing parent: {} of changeset: {}", next, uuid);
+                }
+                return None
+            },
+        }
+    }
+    let empty = Changeset { entries: vec!(), .. Default::default() };
+    Some(chain.into_iter().rev().fold(empty, |state, changeset| apply_delta(state, changeset)))
+}
+
+
+/* full state at given changeset of user, from chain of its changesets read from given store */
+pub fn state_at(store: &SpiceStore, user_name: &str, uuid: Uuid) -> Option<Changeset> {
+    materialize(&store.chain_of(user_name, uuid), uuid)
+}
+
+
+/* local content of all files of changeset, joined into single text for diffs */
+pub fn local_content_of(changeset: &Changeset) -> String {
+    let local_content: Vec<u8> = changeset
+        .entries
+        .iter()
+        .flat_map(|domain| domain.files.iter())
+        .flat_map(|f| content_of(f))
+        .collect();
+    String::from_utf8_lossy(&local_content).into_owned()
+}
+
+
+/* full state of user from its most recent changeset */
+pub fn current_state(user_name: String) -> Changeset {
+    let newest = match SpiceStore::local().index(&user_name) {
+        Ok(index) => index.last().map(|entry| entry.uuid),
+        Err(err) => {
+            error!("Failed to read index of user: {}. Cause: {}", user_name, err);
+            None
+        },
+    };
+    match newest {
+        Some(uuid) => state_at(&SpiceStore::local(), &user_name, uuid).unwrap_or(invalid_changeset()),
+        None => invalid_changeset(),
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn delta_materialize_test() {
+    let file = |path: &str, sha: &str| FileEntry { path: path.to_string(), raw_sha256: sha.to_string(), .. Default::default() };
+    let domain = |name: &str, files: Vec<FileEntry>| DomainEntry { name: name.to_string(), files: files, .. Default::default() };
+
+    let first = Changeset {
+        entries: vec!(
+            domain("a.com", vec!(file("/a/index.php", "1"), file("/a/old.php", "2"))),
+            domain("b.com", vec!(file("/b/index.php", "3"))),
+            domain("c.com", vec!(file("/c/index.php", "4"))),
+        ),
+        .. Default::default()
+    };
+    let scanned = Changeset {
+        entries: vec!(
+            domain("a.com", vec!(file("/a/index.php", "5"), file("/a/new.php", "6"))),
+            domain("b.com", vec!(FileEntry { modified: 123, .. file("/b/index.php", "3") })),
+            domain("d.com", vec!(file("/d/index.php", "7"))),
+        ),
+        .. Default::default()
+    };
+    let second = delta_of(&first, scanned.clone());
+    assert!(second.parent == first.uuid && !second.full);
+    assert!(second.removed == vec!(String::from("c.com")));
+    assert!(second.entries.len() == 2, "Unchanged domain shouldn't be stored in delta");
+    assert!(second.entries[0].name == "a.com" && second.entries[0].files.len() == 2);
+    assert!(second.entries[0].removed_files == vec!(String::from("/a/old.php")));
+    assert!(second.entries[1].name == "d.com");
+
+    let third = delta_of(&apply_delta(first.clone(), &second), Changeset { uuid: Uuid::new_v4(), .. scanned });
+    assert!(third.parent == second.uuid && third.entries.is_empty() && third.removed.is_empty());
+
+    let changesets = vec!(first.clone(), second.clone(), third.clone());
+    let state = materialize(&changesets, third.uuid).unwrap();
+    assert!(state.uuid == third.uuid && state.full);
+    assert!(state.entries.iter().map(|domain| domain.name.clone()).collect::<Vec<String>>() == vec!("a.com", "b.com", "d.com"));
+    let paths: Vec<String> = state.entries[0].files.iter().map(|file| file.path.clone()).collect();
+    assert!(paths == vec!("/a/index.php", "/a/new.php"));
+    assert!(state.entries[0].files[0].raw_sha256 == "5");
+
+    let state = materialize(&changesets, first.uuid).unwrap();
+    assert!(state.entries.len() == 3);
+    assert!(materialize(&changesets[1..], third.uuid).is_none(), "Chain without its root shouldn't materialize");
+    assert!(materialize(&changesets, Uuid::new_v4()).is_none());
+}
+
+
+#[cfg(test)]
+#[test]
+fn delta_state_at_test() {
+    let root = "/tmp/yak-state-at-test";
+    remove_dir_all(root).unwrap_or(());
+    let store = SpiceStore::new(root, "s1");
+    let file = |path: &str, sha: &str| FileEntry { path: path.to_string(), raw_sha256: sha.to_string(), .. Default::default() };
+    let domain = |files: Vec<FileEntry>| DomainEntry { name: String::from("a.com"), files: files, .. Default::default() };
+
+    let first = Changeset { timestamp: 1000, entries: vec!(domain(vec!(file("/a/index.php", "1"), file("/a/lib.php", "2")))), .. Default::default() };
+    let second = delta_of(&first, Changeset { timestamp: 2000, entries: vec!(domain(vec!(file("/a/index.php", "3"), file("/a/lib.php", "2")))), .. Default::default() });
+    let third = delta_of(&apply_delta(first.clone(), &second), Changeset { timestamp: 3000, entries: vec!(domain(vec!(file("/a/index.php", "3"), file("/a/lib.php", "4")))), .. Default::default() });
+    for changeset in vec!(&first, &second, &third) {
+        store.store_changeset("admin7", changeset).unwrap();
+    }
+    assert!(second.entries[0].files.len() == 1 && second.entries[0].files[0].path == "/a/index.php");
+    assert!(third.entries[0].files.len() == 1 && third.entries[0].files[0].path == "/a/lib.php");
+
+    /* each side of diff has whole state, not only files touched by its delta */
+    let shas = |state: Changeset| state.entries[0].files.iter().map(|file| file.raw_sha256.clone()).collect::<Vec<String>>();
+    assert!(shas(state_at(&store, "admin7", second.uuid).unwrap()) == vec!("3", "2"));
+    assert!(shas(state_at(&store, "admin7", third.uuid).unwrap()) == vec!("3", "4"));
+    assert!(state_at(&store, "admin7", Uuid::new_v4()).is_none());
+    assert!(state_at(&store, "nobody", third.uuid).is_none());
+}
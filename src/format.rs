@@ -0,0 +1,316 @@
+use process::*;
+
+
+/* stored changeset: magic, format version (big endian), zlib compressed bincode of Changeset
+   and hex encoded sha256 of all preceding bytes, so truncated or damaged files are detected */
+pub const CHANGESET_MAGIC: &'static [u8] = b"YAKCHG";
+pub const CHANGESET_VERSION: u16 = 2;
+const CHECKSUM_SIZE: usize = 64;
+
+
+/* version 1: headerless format of first releases, with one DomainEntry per scanned file */
+mod v1 {
+    use uuid::Uuid;
+    use structs::Owner;
+
+    #[derive(RustcDecodable, RustcEncodable, Clone)]
+    pub struct FileEntry {
+        pub path: String,
+        pub sha1: String,
+        pub local_content: Vec<u8>,
+        pub lang: String,
+        pub encoding: String,
+        pub owner: Owner,
+        pub size: u64,
+        pub mode: u32,
+        pub modified: i64,
+    }
+
+    #[derive(RustcDecodable, RustcEncodable, Clone)]
+    pub struct DomainEntry {
+        pub name: String,
+        pub request_path: String,
+        pub file: FileEntry,
+        pub http_content: String,
+        pub http_content_encoding: String,
+        pub http_content_size: usize,
+        pub http_status_code: u32,
+        pub http_response_time: u64,
+        pub https_content: String,
+        pub https_content_encoding: String,
+        pub https_content_size: usize,
+        pub https_status_code: u32,
+        pub https_response_time: u64,
+    }
+
+    #[derive(RustcDecodable, RustcEncodable, Clone)]
+    pub struct Changeset {
+        pub uuid: Uuid,
+        pub parent: Uuid,
+        pub timestamp: u64,
+        pub entries: Vec<DomainEntry>,
+    }
+}
+
+
+/* groups files of version 1 changeset by domain. Local contents are returned, to be moved to blob store by migrate_changeset() */
+fn migrate_v1(legacy: v1::Changeset) -> Result<(Changeset, Vec<Vec<u8>>), String> {
+    let mut entries: Vec<DomainEntry> = vec!();
+    let mut contents = vec!();
+    for entry in legacy.entries {
+        let content_sha256 = match raw_checksums_of(&entry.file.local_content[..]) {
+            Ok((_, sha256)) => sha256,
+            Err(err) => return Err(format!("Failed to checksum content of file: {}. Cause: {}", entry.file.path, err)),
+        };
+        contents.push(entry.file.local_content);
+        let file = FileEntry {
+            path: entry.file.path,
+            text_sha1: entry.file.sha1,
+            content_sha256: content_sha256,
+            lang: entry.file.lang,
+            encoding: entry.file.encoding,
+            owner: entry.file.owner,
+            size: entry.file.size,
+            mode: entry.file.mode,
+            modified: entry.file.modified,
+            .. Default::default()
+        };
+        let position = entries.iter().position(|domain| domain.name == entry.name);
+        match position {
+            Some(index) => entries[index].files.push(file),
+            None => entries.push(DomainEntry {
+                name: entry.name,
+                files: vec!(file),
+                http_content: entry.http_content,
+                http_content_encoding: entry.http_content_encoding,
+                http_content_size: entry.http_content_size,
+                http_status_code: entry.http_status_code,
+                http_response_time: entry.http_response_time,
+                https_content: entry.https_content,
+                https_content_encoding: entry.https_content_encoding,
+                https_content_size: entry.https_content_size,
+                https_status_code: entry.https_status_code,
+                https_response_time: entry.https_response_time,
+                .. Default::default()
+            }),
+        }
+    }
+    Ok((Changeset {
+        uuid: legacy.uuid,
+        parent: legacy.parent,
+        timestamp: legacy.timestamp,
+        severity: SeverityLevels::Normal,
+        full: true,
+        removed: vec!(),
+        entries: entries,
+    }, contents))
+}
+
+
+pub fn encode_changeset(changeset: &Changeset) -> Result<Vec<u8>, String> {
+    let binary_encoded = match encode(changeset, SizeLimit::Infinite) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(format!("Failed to encode changeset: {}. Cause: {}", changeset.uuid, err)),
+    };
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::Best);
+    match zlib.write_all(&binary_encoded[..]) {
+        Ok(_) => {},
+        Err(err) => return Err(format!("Failed to compress changeset: {}. Cause: {}", changeset.uuid, err)),
+    }
+    let compressed_bytes = match zlib.finish() {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(format!("Failed to compress changeset: {}. Cause: {}", changeset.uuid, err)),
+    };
+    let mut bytes = CHANGESET_MAGIC.to_vec();
+    bytes.push((CHANGESET_VERSION >> 8) as u8);
+    bytes.push(CHANGESET_VERSION as u8);
+    bytes.extend_from_slice(&compressed_bytes);
+    let checksum = try!(checksum_of(&bytes));
+    bytes.extend_from_slice(checksum.as_bytes());
+    Ok(bytes)
+}
+
+
+fn checksum_of(bytes: &[u8]) -> Result<String, String> {
+    match raw_checksums_of(bytes) {
+        Ok((_, sha256)) => Ok(sha256),
+        Err(err) => Err(format!("Failed to checksum changeset. Cause: {}", err)),
+    }
+}
+
+
+/* payload of current format version, if its trailing checksum is valid */
+fn verified_payload(bytes: &[u8], header_size: usize) -> Result<&[u8], String> {
+    if bytes.len() < header_size + CHECKSUM_SIZE {
+        return Err(format!("Changeset truncated to: {} bytes", bytes.len()))
+    }
+    let (content, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+    if try!(checksum_of(content)).as_bytes() != checksum {
+        return Err(String::from("Checksum mismatch. Changeset is damaged or truncated"))
+    }
+    Ok(&content[header_size..])
+}
+
+
+/* decodes changeset stored in any known format version, migrating it to current structs. Contents of legacy files aren't stored */
+pub fn decode_changeset(bytes: &[u8]) -> Result<Changeset, String> {
+    decode_with_contents(bytes).map(|(changeset, _)| changeset)
+}
+
+
+/* decodes changeset of any known format version and moves file contents embedded in legacy versions to blob store */
+pub fn migrate_changeset(bytes: &[u8], blobs_dir: &str) -> Result<Changeset, String> {
+    let (changeset, contents) = try!(decode_with_contents(bytes));
+    for content in contents {
+        try!(store_blob(blobs_dir, &content));
+    }
+    Ok(changeset)
+}
+
+
+/* changeset with file contents embedded in legacy format versions */
+fn decode_with_contents(bytes: &[u8]) -> Result<(Changeset, Vec<Vec<u8>>), String> {
+    let header_size = CHANGESET_MAGIC.len() + 2;
+    let (version, payload) = if bytes.starts_with(CHANGESET_MAGIC) && bytes.len() >= header_size {
+        let version = ((bytes[CHANGESET_MAGIC.len()] as u16) << 8) | bytes[CHANGESET_MAGIC.len() + 1] as u16;
+        (version, &bytes[header_size..])
+    } else {
+        (1, bytes)
+    };
+    let mut decoder = ZlibDecoder::new(payload);
+    match version {
+        1 => match decode_from(&mut decoder, SizeLimit::Infinite) {
+            Ok(legacy) => migrate_v1(legacy),
+            Err(err) => Err(format!("Failed to decode changeset of format version: 1. Cause: {}", err)),
+        },
+        CHANGESET_VERSION => {
+            let mut decoder = ZlibDecoder::new(try!(verified_payload(bytes, header_size)));
+            match decode_from(&mut decoder, SizeLimit::Infinite) {
+                Ok(changeset) => Ok((changeset, vec!())),
+                Err(err) => Err(format!("Failed to decode changeset of format version: {}. Cause: {}", version, err)),
+            }
+        },
+        unknown => Err(format!("Unsupported changeset format version: {}. Supported versions: 1, {}", unknown, CHANGESET_VERSION)),
+    }
+}
+
+
+/* version 1 changeset with single file, as stored by first releases */
+#[cfg(test)]
+pub fn encode_v1(uuid: Uuid, timestamp: u64, domain: &str, path: &str, content: &[u8]) -> Vec<u8> {
+    let legacy = v1::Changeset {
+        uuid: uuid,
+        parent: root_uuid(),
+        timestamp: timestamp,
+        entries: vec!(v1::DomainEntry {
+            name: domain.to_string(),
+            request_path: String::from("/"),
+            file: v1::FileEntry {
+                path: path.to_string(),
+                sha1: String::new(),
+                local_content: content.to_vec(),
+                lang: String::from("en"),
+                encoding: String::from("ASCII"),
+                owner: Owner::default(),
+                size: content.len() as u64,
+                mode: 0o644,
+                modified: 0,
+            },
+            http_content: String::new(),
+            http_content_encoding: String::new(),
+            http_content_size: 0,
+            http_status_code: 200,
+            http_response_time: 0,
+            https_content: String::new(),
+            https_content_encoding: String::new(),
+            https_content_size: 0,
+            https_status_code: 200,
+            https_response_time: 0,
+        }),
+    };
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::Best);
+    zlib.write_all(&encode(&legacy, SizeLimit::Infinite).unwrap()).unwrap();
+    zlib.finish().unwrap()
+}
+
+
+#[cfg(test)]
+#[test]
+fn decode_changeset_test() {
+    use blobs::load_blob;
+
+    let changeset = Changeset {
+        entries: vec!(DomainEntry {
+            name: String::from("example.com"),
+            files: vec!(FileEntry { path: String::from("/tmp/index.php"), .. Default::default() }),
+            .. Default::default()
+        }),
+        .. Default::default()
+    };
+    let bytes = encode_changeset(&changeset).unwrap();
+    assert!(bytes.starts_with(CHANGESET_MAGIC));
+    let decoded = decode_changeset(&bytes).unwrap();
+    assert!(decoded.uuid == changeset.uuid && decoded.entries[0].files[0].path == "/tmp/index.php");
+    assert!(decode_changeset(&bytes[..bytes.len() - 1]).is_err(), "Truncated changeset shouldn't decode");
+    let mut damaged = bytes.clone();
+    damaged[CHANGESET_MAGIC.len() + 4] ^= 0xff;
+    assert!(decode_changeset(&damaged).is_err(), "Damaged changeset shouldn't decode");
+
+    let legacy_file = |path: &str| v1::FileEntry {
+        path: path.to_string(),
+        sha1: String::from("a9993e364706816aba3e25717850c26c9cd0d89d"),
+        local_content: format!("<?php // {}", path).into_bytes(),
+        lang: String::from("en"),
+        encoding: String::from("utf-8"),
+        owner: Owner::default(),
+        size: 3,
+        mode: 0o644,
+        modified: 60,
+    };
+    let legacy_entry = |name: &str, path: &str| v1::DomainEntry {
+        name: name.to_string(),
+        request_path: String::from("/"),
+        file: legacy_file(path),
+        http_content: String::new(),
+        http_content_encoding: String::new(),
+        http_content_size: 0,
+        http_status_code: 200,
+        http_response_time: 10,
+        https_content: String::new(),
+        https_content_encoding: String::new(),
+        https_content_size: 0,
+        https_status_code: 410,
+        https_response_time: 0,
+    };
+    let legacy = v1::Changeset {
+        uuid: Uuid::new_v4(),
+        parent: root_uuid(),
+        timestamp: 1464000000000,
+        entries: vec!(
+            legacy_entry("example.com", "/home/admin6/domains/example.com/public_html/index.php"),
+            legacy_entry("example.com", "/home/admin6/domains/example.com/public_html/main.php"),
+            legacy_entry("example.org", "/home/admin6/domains/example.org/public_html/index.php"),
+        ),
+    };
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::Best);
+    zlib.write_all(&encode(&legacy, SizeLimit::Infinite).unwrap()).unwrap();
+    let legacy_bytes = zlib.finish().unwrap();
+    let blobs_dir = "/tmp/yak-format-blobs";
+    remove_dir_all(blobs_dir).unwrap_or(());
+    let decoded = decode_changeset(&legacy_bytes).unwrap();
+    assert!(!Path::new(blobs_dir).exists(), "Decode shouldn't store contents");
+    let migrated = migrate_changeset(&legacy_bytes, blobs_dir).unwrap();
+    assert!(migrated.uuid == legacy.uuid && migrated.timestamp == legacy.timestamp && migrated.full);
+    assert!(migrated.entries.len() == 2);
+    assert!(migrated.entries[0].files.len() == 2 && migrated.entries[0].http_status_code == 200);
+    assert!(migrated.entries[1].files[0].text_sha1 == "a9993e364706816aba3e25717850c26c9cd0d89d");
+    let content_sha256 = &migrated.entries[1].files[0].content_sha256;
+    assert!(decoded.entries[1].files[0].content_sha256 == *content_sha256);
+    assert!(load_blob(blobs_dir, content_sha256).unwrap() == b"<?php // /home/admin6/domains/example.org/public_html/index.php".to_vec());
+
+    let mut future = CHANGESET_MAGIC.to_vec();
+    future.extend_from_slice(&[0, 99]);
+    future.extend_from_slice(&encode_changeset(&changeset).unwrap()[CHANGESET_MAGIC.len() + 2..]);
+    assert!(decode_changeset(&future).is_err());
+    assert!(decode_changeset(b"garbage").is_err());
+}
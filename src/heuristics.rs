@@ -0,0 +1,156 @@
+use process::*;
+
+
+/*
+    Weights of each heuristic, sum of all is maximal suspicion score: 100.
+    Each heuristic gives full weight above "high" limit, and half of it above "low" limit.
+ */
+const ENTROPY_WEIGHT: u32 = 25;
+const LONGEST_LINE_WEIGHT: u32 = 20;
+const NON_ALPHANUMERIC_WEIGHT: u32 = 15;
+const OBFUSCATION_DENSITY_WEIGHT: u32 = 20;
+const VARIABLE_FUNCTIONS_WEIGHT: u32 = 20;
+
+
+/* shannon entropy in bits per byte: 0.0 - 8.0. Plain source code is usually below 5.3 */
+pub fn entropy_of(content: &[u8]) -> f64 {
+    if content.is_empty() {
+        return 0.0
+    }
+    let mut counts = [0usize; 256];
+    for byte in content {
+        counts[*byte as usize] += 1;
+    }
+    let length = content.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let probability = *count as f64 / length;
+            -probability * probability.log2()
+        })
+        .fold(0.0, |sum, e| sum + e)
+}
+
+
+pub fn longest_line_of(content: &[u8]) -> usize {
+    content.split(|byte| *byte == b'\n').map(|line| line.len()).max().unwrap_or(0)
+}
+
+
+/* ratio of characters that are neither alphanumeric nor whitespace */
+pub fn non_alphanumeric_ratio(text: &str) -> f64 {
+    let visible: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if visible.is_empty() {
+        return 0.0
+    }
+    visible.iter().filter(|c| !c.is_alphanumeric()).count() as f64 / visible.len() as f64
+}
+
+
+/* chr() and String.fromCharCode() calls, hex escapes and string concatenations per 1KiB of content */
+pub fn obfuscation_density(text: &str) -> f64 {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r#"(?i)\bchr\s*\(|fromCharCode\s*\(|\\x[0-9a-f]{2}|["']\s*[.+]\s*["']"#).unwrap();
+    }
+    if text.is_empty() {
+        return 0.0
+    }
+    RE.find_iter(text).count() as f64 * 1024.0 / text.len() as f64
+}
+
+
+/* calls of functions which names are held in variables, f.e.: $f($x), ${"a"}(), $a['b'](), window["eval"]() */
+pub fn variable_function_calls(text: &str) -> usize {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\$[A-Za-z_][A-Za-z0-9_]*\s*\(|\$\{[^}]*\}\s*\(|\$[A-Za-z_][A-Za-z0-9_]*\[[^\]]*\]\s*\(|\b(window|this|self|global)\[[^\]]+\]\s*\(").unwrap();
+    }
+    RE.find_iter(text).count()
+}
+
+
+/* heuristics are tuned for PHP and JavaScript sources only */
+pub fn heuristics_applicable(name: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\.(php[0-9]*|phtml|inc|js)$").unwrap();
+    }
+    RE.is_match(name)
+}
+
+
+fn weighted(value: f64, low: f64, high: f64, weight: u32) -> u32 {
+    if value >= high {
+        weight
+    } else if value >= low {
+        weight / 2
+    } else {
+        0
+    }
+}
+
+
+/* suspicion score of obfuscated code: 0 - 100 */
+pub fn suspicion_of(name: &str, content: &[u8]) -> u32 {
+    if !heuristics_applicable(name) {
+        return 0
+    }
+    let text = String::from_utf8_lossy(content);
+    let score =
+        weighted(entropy_of(content), 5.3, 5.8, ENTROPY_WEIGHT) +
+        weighted(longest_line_of(content) as f64, 1000.0, 5000.0, LONGEST_LINE_WEIGHT) +
+        weighted(non_alphanumeric_ratio(&text), 0.35, 0.45, NON_ALPHANUMERIC_WEIGHT) +
+        weighted(obfuscation_density(&text), 1.0, 5.0, OBFUSCATION_DENSITY_WEIGHT) +
+        weighted(variable_function_calls(&text) as f64, 1.0, 3.0, VARIABLE_FUNCTIONS_WEIGHT);
+    debug!("Suspicion score of: {} is: {}", name, score);
+    score
+}
+
+
+/* file should be reported, if it matched any signature or its suspicion score reached given threshold */
+pub fn is_suspicious(entry: &FileEntry, threshold: u32) -> bool {
+    !entry.signature_hits.is_empty() || entry.suspicion >= threshold
+}
+
+
+#[cfg(test)]
+#[test]
+fn suspicion_of_test() {
+    let plain = br#"<?php
+/**
+ * Front to the WordPress application. This file doesn't do anything, but loads
+ * wp-blog-header.php which does and tells WordPress to load the theme.
+ */
+define('WP_USE_THEMES', true);
+
+/** Loads the WordPress Environment and Template */
+require( dirname( __FILE__ ) . '/wp-blog-header.php' );
+"#;
+    assert!(suspicion_of("index.php", plain) < Config::default().suspicion_threshold);
+    assert!(variable_function_calls(&String::from_utf8_lossy(plain)) == 0);
+
+    let mut obfuscated = String::from("<?php $GLOBALS['_x']=array(chr(98).chr(97).'s'.'e'.'6'.'4'.'_'.'d'.'e'.'c'.'o'.'d'.'e');");
+    obfuscated.push_str("$f=$GLOBALS['_x'][0];$g=\"\\x67\\x7a\\x69\\x6e\\x66\\x6c\\x61\\x74\\x65\";@eval($g($f('");
+    /* pseudo random base64 payload */
+    let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut seed: u64 = 7;
+    for _ in 0..6000 {
+        seed = (seed * 1103515245 + 12345) % 2147483648;
+        obfuscated.push(alphabet[((seed >> 16) % 64) as usize] as char);
+    }
+    obfuscated.push_str("')));${'_'.'x'}();");
+    let score = suspicion_of("wp-content/uploads/cache.php", obfuscated.as_bytes());
+    assert!(score >= Config::default().suspicion_threshold, format!("Score: {}", score));
+    assert!(suspicion_of("wp-content/uploads/cache.txt", obfuscated.as_bytes()) == 0);
+    assert!(variable_function_calls(&obfuscated) == 3);
+
+    assert!(entropy_of(b"") == 0.0);
+    assert!(entropy_of(b"aaaa") == 0.0);
+    assert!(entropy_of(b"abab") == 1.0);
+    assert!(longest_line_of(b"a\nbbb\ncc") == 3);
+    assert!(non_alphanumeric_ratio("a b;") == 1.0 / 3.0);
+
+    let mut entry = FileEntry { suspicion: 40, .. Default::default() };
+    assert!(!is_suspicious(&entry, 50));
+    entry.suspicion = 50;
+    assert!(is_suspicious(&entry, 50));
+}
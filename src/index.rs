@@ -0,0 +1,236 @@
+use process::*;
+use store::{manifest_file_name, write_atomically};
+
+use std::collections::HashSet;
+
+
+/* summary of stored changeset, so it can be found without decoding whole history of user */
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub uuid: Uuid,
+    pub parent: Uuid,
+    pub timestamp: u64,
+    pub full: bool,
+    pub parts: Vec<String>, /* changeset part files listed in manifest, relative to user dir */
+    pub domains: usize,
+    pub files: usize,
+    pub removed: usize,
+    pub bytes: usize,
+}
+
+
+impl IndexEntry {
+    pub fn of(changeset: &Changeset, parts: Vec<String>, bytes: usize) -> IndexEntry {
+        IndexEntry {
+            uuid: changeset.uuid,
+            parent: changeset.parent,
+            timestamp: changeset.timestamp,
+            full: changeset.full,
+            parts: parts,
+            domains: changeset.entries.len(),
+            files: changeset.entries.iter().fold(0, |count, entry| count + entry.files.len()),
+            removed: changeset.removed.len(),
+            bytes: bytes,
+        }
+    }
+}
+
+
+impl SpiceStore {
+    pub fn index_file(&self, user_name: &str) -> String {
+        format!("{}/index.json", self.user_dir(user_name))
+    }
+
+    fn read_index(&self, user_name: &str) -> Option<Vec<IndexEntry>> {
+        let file_name = self.index_file(user_name);
+        let mut content = String::new();
+        let read = File::open(&file_name).and_then(|file| BufReader::new(file).read_to_string(&mut content));
+        match read {
+            Ok(_) => match json::decode(&content) {
+                Ok(index) => Some(index),
+                Err(err) => {
+                    warn!("Failed to decode index: {}. Cause: {}", file_name, err);
+                    None
+                },
+            },
+            Err(err) => {
+                debug!("No index: {}. Cause: {}", file_name, err);
+                None
+            },
+        }
+    }
+
+    fn write_index(&self, user_name: &str, index: &[IndexEntry]) -> Result<(), String> {
+        let file_name = self.index_file(user_name);
+        let encoded = match json::encode(&index) {
+            Ok(encoded) => encoded,
+            Err(err) => return Err(format!("Failed to encode index: {}. Cause: {}", file_name, err)),
+        };
+        match write_atomically(&file_name, encoded.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Failed to write index: {}. Cause: {}", file_name, err)),
+        }
+    }
+
+    /* timestamp sorted index of user. Rebuilt from changesets if it's missing or doesn't match their manifests */
+    pub fn index(&self, user_name: &str) -> Result<Vec<IndexEntry>, String> {
+        let stored: HashSet<String> = self.manifest_files(user_name)
+            .into_iter()
+            .filter_map(|path| path.file_name().and_then(|name| name.to_str()).map(|name| name.to_string()))
+            .collect();
+        match self.read_index(user_name) {
+            Some(index) => {
+                let indexed: HashSet<String> = index.iter().map(|entry| manifest_file_name(entry.timestamp, entry.uuid)).collect();
+                if indexed == stored {
+                    return Ok(index)
+                }
+                warn!("Index of user: {} is out of date", user_name);
+            },
+            None => {},
+        }
+        self.rebuild_index(user_name)
+    }
+
+    /* decodes all changesets of user to write index again */
+    pub fn rebuild_index(&self, user_name: &str) -> Result<Vec<IndexEntry>, String> {
+        info!("Rebuilding index of user: {}", user_name);
+        let index: Vec<IndexEntry> = try!(self.assemble(user_name))
+            .into_iter()
+            .map(|(changeset, parts, bytes)| IndexEntry::of(&changeset, parts, bytes))
+            .collect();
+        match self.write_index(user_name, &index) {
+            Ok(_) => {},
+            Err(err) => error!("{}", err),
+        }
+        Ok(index)
+    }
+
+    /* adds stored changeset to index. Index that doesn't exist yet is built by first read instead */
+    pub fn index_changeset(&self, user_name: &str, changeset: &Changeset, parts: Vec<String>, bytes: usize) {
+        let mut index = match self.read_index(user_name) {
+            Some(index) => index,
+            None => return,
+        };
+        let mut parts = parts;
+        parts.sort();
+        index.retain(|entry| entry.uuid != changeset.uuid);
+        index.push(IndexEntry::of(changeset, parts, bytes));
+        index.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        match self.write_index(user_name, &index) {
+            Ok(_) => {},
+            Err(err) => warn!("{}", err), /* stale index is rebuilt on next read */
+        }
+    }
+
+    pub fn unindex_changeset(&self, user_name: &str, uuid: Uuid) {
+        let mut index = match self.read_index(user_name) {
+            Some(index) => index,
+            None => return,
+        };
+        index.retain(|entry| entry.uuid != uuid);
+        match self.write_index(user_name, &index) {
+            Ok(_) => {},
+            Err(err) => warn!("{}", err),
+        }
+    }
+
+    /* reads only parts of indexed changeset */
+    fn read_indexed(&self, user_name: &str, entry: &IndexEntry) -> Option<Changeset> {
+        let manifest_file = self.manifest_file(user_name, entry.timestamp, entry.uuid);
+        match self.read_committed(user_name, Path::new(&manifest_file)) {
+            Ok((changeset, _, _)) => Some(changeset),
+            Err(err) => {
+                error!("Failed to read changeset: {}. Cause: {}", manifest_file, err);
+                None
+            },
+        }
+    }
+
+    /* index of user for lookups, which treat index that can't be read as empty */
+    fn lookup_index(&self, user_name: &str) -> Vec<IndexEntry> {
+        match self.index(user_name) {
+            Ok(index) => index,
+            Err(err) => {
+                error!("Failed to read index of user: {}. Cause: {}", user_name, err);
+                vec!()
+            },
+        }
+    }
+
+    /* single changeset of user, without reading any other */
+    pub fn changeset(&self, user_name: &str, uuid: Uuid) -> Option<Changeset> {
+        let index = self.lookup_index(user_name);
+        let found = index.iter().find(|entry| entry.uuid == uuid).map(|entry| self.read_indexed(user_name, entry));
+        found.unwrap_or(None)
+    }
+
+    /* timestamp sorted changesets of user, created in given time window (inclusive) */
+    pub fn changesets_between(&self, user_name: &str, since: u64, until: u64) -> Vec<Changeset> {
+        self.lookup_index(user_name)
+            .iter()
+            .filter(|entry| entry.timestamp >= since && entry.timestamp <= until)
+            .filter_map(|entry| self.read_indexed(user_name, entry))
+            .collect()
+    }
+
+    /* timestamp sorted changesets needed to materialize given one: nearest full snapshot and all deltas after it */
+    pub fn chain_of(&self, user_name: &str, uuid: Uuid) -> Vec<Changeset> {
+        let index = self.lookup_index(user_name);
+        let mut chain = vec!();
+        let mut next = uuid;
+        loop {
+            let entry = match index.iter().find(|entry| entry.uuid == next) {
+                Some(entry) => entry,
+                None => break, /* broken chain, reported by verify */
+            };
+            match self.read_indexed(user_name, entry) {
+                Some(changeset) => chain.push(changeset),
+                None => break,
+            }
+            if entry.full {
+                break
+            }
+            next = entry.parent;
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn changeset_index_test() {
+    let root = "/tmp/yak-index-test";
+    remove_dir_all(root).unwrap_or(());
+    let store = SpiceStore::new(root, "s1");
+
+    let domain = |name: &str| DomainEntry { name: name.to_string(), files: vec!(FileEntry { path: format!("/{}/index.php", name), .. Default::default() }), .. Default::default() };
+    let first = Changeset { timestamp: 1000, entries: vec!(domain("b.com"), domain("a.com")), .. Default::default() };
+    let second = Changeset { timestamp: 2000, parent: first.uuid, full: false, removed: vec!(String::from("b.com")), .. Default::default() };
+    let third = Changeset { timestamp: 3000, parent: second.uuid, full: false, .. Default::default() };
+    store.store_changeset("admin6", &first).unwrap();
+    assert!(!Path::new(&store.index_file("admin6")).exists(), "Index should be built by first read");
+
+    let index = store.index("admin6").unwrap();
+    assert!(index.len() == 1 && index[0].parts.len() == 2 && index[0].domains == 2 && index[0].files == 2);
+    store.store_changeset("admin6", &second).unwrap();
+    store.store_changeset("admin6", &third).unwrap();
+    assert!(store.read_index("admin6").unwrap().len() == 3, "Index should be updated by each write");
+    assert!(store.index("admin6") == store.rebuild_index("admin6"));
+
+    let found = store.changeset("admin6", second.uuid).unwrap();
+    assert!(found.removed == vec!(String::from("b.com")));
+    assert!(store.changeset("admin6", Uuid::new_v4()).is_none());
+    let window = store.changesets_between("admin6", 1500, 3000);
+    assert!(window.len() == 2 && window[0].uuid == second.uuid);
+    let chain = store.chain_of("admin6", third.uuid);
+    assert!(chain.len() == 3 && chain[0].uuid == first.uuid && chain[0].entries[0].name == "a.com");
+
+    /* changesets removed behind the index back */
+    remove_file(&store.manifest_file("admin6", third.timestamp, third.uuid)).unwrap();
+    assert!(store.index("admin6").unwrap().len() == 2);
+    store.remove_changeset("admin6", &second).unwrap();
+    assert!(store.read_index("admin6").unwrap().len() == 1);
+    assert!(store.chain_of("admin6", first.uuid).len() == 1);
+}
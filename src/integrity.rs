@@ -0,0 +1,151 @@
+use process::*;
+use spice::SpiceBaseline;
+
+use std::collections::HashSet;
+
+
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub enum FsDiffKind {
+    Modified,   /* framework file checksum differs from pristine one */
+    Missing,    /* framework file not found in document root */
+    Extra,      /* unknown file found in framework directory */
+}
+
+
+/* single framework file that differs from pristine web app release */
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub struct FsDiff {
+    pub path: String, /* relative to domain document root */
+    pub kind: FsDiffKind,
+}
+
+
+fn raw_sha1_of_file(path: &Path) -> Option<String> {
+    match File::open(path) {
+        Ok(file) => {
+            match raw_checksums_of(BufReader::new(file)) {
+                Ok((raw_sha1, _)) => Some(raw_sha1),
+                Err(err) => {
+                    warn!("Failed to read file: {:?}. Cause: {}", path, err);
+                    None
+                },
+            }
+        },
+        Err(err) => {
+            warn!("Failed to open file: {:?}. Cause: {}", path, err);
+            None
+        },
+    }
+}
+
+
+/* directory part of relative path, "" for files in document root */
+fn dir_of(relative_path: &str) -> String {
+    match relative_path.rfind('/') {
+        Some(index) => relative_path[..index].to_string(),
+        None => String::new(),
+    }
+}
+
+
+/* checksum of file from previous scan if file didn't change since, computed otherwise */
+fn raw_sha1_of(path: &Path, previous_files: &HashMap<String, FileEntry>) -> Option<String> {
+    let previous = path.to_str().and_then(|abs_path| previous_files.get(abs_path));
+    match (previous, path.metadata()) {
+        (Some(previous), Ok(ref metadata)) if unchanged_file(previous, metadata) => Some(previous.raw_sha1.clone()),
+        _ => raw_sha1_of_file(path),
+    }
+}
+
+
+/* compares framework files in given document root with pristine baseline. Files unchanged since previous scan aren't read again */
+pub fn verify_integrity(docroot: &str, baseline: &SpiceBaseline, previous_files: &HashMap<String, FileEntry>) -> Vec<FsDiff> {
+    let mut diffs = vec!();
+    let framework_dirs: HashSet<String> = baseline.checksums.keys().map(|path| dir_of(path)).collect();
+    let docroot = docroot.trim_right_matches('/');
+
+    for (relative_path, checksum) in baseline.checksums.iter() {
+        let path = Path::new(docroot).join(relative_path);
+        if !path.is_file() {
+            diffs.push(FsDiff { path: relative_path.clone(), kind: FsDiffKind::Missing });
+            continue
+        }
+        match raw_sha1_of(&path, previous_files) {
+            Some(ref raw_sha1) if raw_sha1 == checksum => {},
+            _ => diffs.push(FsDiff { path: relative_path.clone(), kind: FsDiffKind::Modified }),
+        }
+    }
+
+    let walker = WalkDir::new(docroot)
+        .follow_links(false)
+        .max_open(256)
+        .into_iter();
+
+    for entry in walker
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file()) {
+
+        let abs_path = entry.path().to_str().unwrap_or("");
+        if abs_path.len() <= docroot.len() || !valid_file_extensions(abs_path) {
+            continue
+        }
+        let relative_path = abs_path[docroot.len()..].trim_left_matches('/');
+        if baseline.checksum_of(relative_path).is_none() && framework_dirs.contains(&dir_of(relative_path)) {
+            diffs.push(FsDiff { path: relative_path.to_string(), kind: FsDiffKind::Extra });
+        }
+    }
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+    debug!("Integrity of: {} against: {:?} {}: {} diffs", docroot, baseline.app_type, baseline.version, diffs.len());
+    diffs
+}
+
+
+#[cfg(test)]
+#[test]
+fn verify_integrity_test() {
+    use spice::gather_baseline;
+    use std::fs::remove_file;
+
+    let root = "/tmp/yak-integrity";
+    remove_dir_all(Path::new(root)).unwrap_or(());
+    let pristine = vec!(
+        ("index.php", "<?php require('./wp-blog-header.php');"),
+        ("wp-login.php", "<?php // login"),
+        ("wp-includes/version.php", "<?php $wp_version = '4.5.2';"),
+        ("wp-includes/load.php", "<?php // load"),
+    );
+    for &(dir, ref files) in vec!(("pristine", pristine.clone()), ("docroot", pristine)).iter() {
+        for &(file, content) in files.iter() {
+            let path = Path::new(root).join(dir).join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+        }
+    }
+    let baseline = gather_baseline(&format!("{}/pristine", root), WebAppTypes::WordPress, "4.5.2", "5.2", true).unwrap();
+    let docroot = format!("{}/docroot", root);
+    assert!(verify_integrity(&docroot, &baseline, &HashMap::new()).is_empty());
+
+    /* payload appended to core file, core file removed, shell dropped into core dir and in uploads */
+    OpenOptions::new().append(true).open(format!("{}/wp-includes/load.php", docroot)).unwrap()
+        .write_all(b"<?php eval($_POST['x']);").unwrap();
+    remove_file(format!("{}/wp-login.php", docroot)).unwrap();
+    File::create(format!("{}/wp-includes/shell.php", docroot)).unwrap();
+    create_dir_all(format!("{}/wp-content/uploads", docroot)).unwrap();
+    File::create(format!("{}/wp-content/uploads/photo.php", docroot)).unwrap();
+
+    let diffs = verify_integrity(&docroot, &baseline, &HashMap::new());
+    assert!(diffs == vec!(
+        FsDiff { path: String::from("wp-includes/load.php"), kind: FsDiffKind::Modified },
+        FsDiff { path: String::from("wp-includes/shell.php"), kind: FsDiffKind::Extra },
+        FsDiff { path: String::from("wp-login.php"), kind: FsDiffKind::Missing },
+    ), format!("Diffs: {:?}", diffs));
+
+    /* checksums of files unchanged since previous scan are taken from it */
+    let index_path = format!("{}/index.php", docroot);
+    let metadata = Path::new(&index_path).metadata().unwrap();
+    let previous = FileEntry { path: index_path.clone(), raw_sha1: String::from("checksum-from-previous-scan"), size: metadata.size(), mtime: metadata.mtime(), inode: metadata.ino(), .. Default::default() };
+    let mut previous_files = HashMap::new();
+    previous_files.insert(index_path.clone(), previous);
+    let diffs = verify_integrity(&docroot, &baseline, &previous_files);
+    assert!(diffs.len() == 4 && diffs[0] == FsDiff { path: String::from("index.php"), kind: FsDiffKind::Modified }, format!("Diffs: {:?}", diffs));
+}
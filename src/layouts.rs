@@ -0,0 +1,420 @@
+use process::*;
+
+use std::fs::read_dir;
+use users::get_user_by_name;
+
+
+/* document root of single domain */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Docroot {
+    pub domain: String,
+    pub path: String,
+}
+
+
+/* depth of document root below user domains dir ({domain}/public_html), where max_depth is counted from */
+pub const DOCROOT_DEPTH: usize = 2;
+
+
+/* knows where document roots of user domains are located on given host */
+pub trait HostingLayout: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /* all document roots of domains owned by given user */
+    fn docroots(&self, user_name: &str) -> Vec<Docroot>;
+}
+
+
+/* names of all subdirectories of given dir, sorted */
+fn subdirs_of(dir: &str) -> Vec<String> {
+    let mut names = vec!();
+    match read_dir(dir) {
+        Ok(entries) => {
+            for entry in entries.filter_map(|e| e.ok()) {
+                match entry.metadata() {
+                    Ok(ref metadata) if metadata.is_dir() => {
+                        match entry.file_name().to_str() {
+                            Some(name) => names.push(name.to_string()),
+                            None => warn!("Skipping non UTF-8 dir: {:?}", entry.path()),
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        },
+        Err(err) => debug!("Can't list dir: {}. Cause: {}", dir, err),
+    }
+    names.sort();
+    names
+}
+
+
+/* DirectAdmin: /home/{user}/domains/{domain}/public_html */
+pub struct DirectAdmin {
+    pub home_dir: String,
+}
+
+
+impl DirectAdmin {
+    /* special DirectAdmin dirs that aren't real domains */
+    pub fn skipped(name: &str) -> bool {
+        match name {
+            "" | "sharedip" | "default" | "suspended" => true,
+            _ => false,
+        }
+    }
+}
+
+
+impl HostingLayout for DirectAdmin {
+    fn name(&self) -> &'static str {
+        "directadmin"
+    }
+
+    fn docroots(&self, user_name: &str) -> Vec<Docroot> {
+        let domains_dir = format!("{}/domains", self.home_dir.replace("{user}", user_name).trim_right_matches('/'));
+        subdirs_of(&domains_dir)
+            .into_iter()
+            .filter(|domain| !DirectAdmin::skipped(domain))
+            .map(|domain| Docroot {
+                path: format!("{}/{}/public_html", domains_dir, domain),
+                domain: domain,
+            })
+            .filter(|docroot| Path::new(&docroot.path).is_dir())
+            .collect()
+    }
+}
+
+
+/* cPanel: main domain in /home/{user}/public_html, addon and sub domains read from userdata */
+pub struct CPanel {
+    pub home_dir: String,
+    pub userdata_dir: String, /* usually: /var/cpanel/userdata */
+}
+
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CPanelUserdata {
+    pub main_domain: String,
+    pub addon_domains: Vec<(String, String)>, /* (addon domain, subdomain that backs it) */
+    pub sub_domains: Vec<String>,
+}
+
+
+/* parses "main" userdata file of cPanel user (simple YAML subset) */
+pub fn parse_cpanel_userdata(content: &str) -> CPanelUserdata {
+    let mut userdata = CPanelUserdata::default();
+    let mut section = String::new();
+    for line in content.lines() {
+        if line.trim().is_empty() || line.starts_with("---") {
+            continue
+        }
+        let indented = line.starts_with(" ") || line.starts_with("\t");
+        let trimmed = line.trim();
+        if !indented {
+            let mut key_value = trimmed.splitn(2, ':');
+            let key = key_value.next().unwrap_or("").trim();
+            let value = key_value.next().unwrap_or("").trim();
+            section = key.to_string();
+            if key == "main_domain" {
+                userdata.main_domain = value.to_string();
+            }
+            continue
+        }
+        match section.as_str() {
+            "addon_domains" => {
+                let mut key_value = trimmed.splitn(2, ':');
+                let addon = key_value.next().unwrap_or("").trim();
+                let sub = key_value.next().unwrap_or("").trim();
+                if !addon.is_empty() {
+                    userdata.addon_domains.push((addon.to_string(), sub.to_string()));
+                }
+            },
+            "sub_domains" => {
+                let sub = trimmed.trim_left_matches('-').trim();
+                if !sub.is_empty() {
+                    userdata.sub_domains.push(sub.to_string());
+                }
+            },
+            _ => {},
+        }
+    }
+    userdata
+}
+
+
+fn read_to_string(path: &str) -> Option<String> {
+    match File::open(path) {
+        Ok(file) => {
+            let mut content = String::new();
+            match BufReader::new(file).read_to_string(&mut content) {
+                Ok(_) => Some(content),
+                Err(err) => {
+                    warn!("Failed to read: {}. Cause: {}", path, err);
+                    None
+                },
+            }
+        },
+        Err(err) => {
+            debug!("Failed to open: {}. Cause: {}", path, err);
+            None
+        },
+    }
+}
+
+
+impl CPanel {
+    /* reads "documentroot" value from userdata file of given domain */
+    fn documentroot_of(&self, user_name: &str, domain: &str) -> Option<String> {
+        let content = match read_to_string(&format!("{}/{}/{}", self.userdata_dir, user_name, domain)) {
+            Some(content) => content,
+            None => return None,
+        };
+        content
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| line.starts_with("documentroot:"))
+            .map(|line| line["documentroot:".len()..].trim().to_string())
+    }
+}
+
+
+impl HostingLayout for CPanel {
+    fn name(&self) -> &'static str {
+        "cpanel"
+    }
+
+    fn docroots(&self, user_name: &str) -> Vec<Docroot> {
+        let home_dir = self.home_dir.replace("{user}", user_name);
+        let userdata = match read_to_string(&format!("{}/{}/main", self.userdata_dir, user_name)) {
+            Some(content) => parse_cpanel_userdata(&content),
+            None => return vec!(),
+        };
+        let mut docroots: Vec<Docroot> = vec!();
+        if !userdata.main_domain.is_empty() {
+            docroots.push(Docroot {
+                domain: userdata.main_domain.clone(),
+                path: format!("{}/public_html", home_dir.trim_right_matches('/')),
+            });
+        }
+        /* addon domains first, so subdomains backing them are not listed twice */
+        for &(ref addon, ref sub) in userdata.addon_domains.iter() {
+            match self.documentroot_of(user_name, sub) {
+                Some(path) => docroots.push(Docroot { domain: addon.clone(), path: path }),
+                None => warn!("No documentroot for addon domain: {} of user: {}", addon, user_name),
+            }
+        }
+        for sub in userdata.sub_domains.iter() {
+            match self.documentroot_of(user_name, sub) {
+                Some(path) => {
+                    if !docroots.iter().any(|e| e.path == path) {
+                        docroots.push(Docroot { domain: sub.clone(), path: path });
+                    }
+                },
+                None => warn!("No documentroot for subdomain: {} of user: {}", sub, user_name),
+            }
+        }
+        docroots
+            .into_iter()
+            .filter(|docroot| Path::new(&docroot.path).is_dir())
+            .collect()
+    }
+}
+
+
+/* Plesk: /var/www/vhosts/{domain}/httpdocs, owned by system user of subscription */
+pub struct Plesk {
+    pub vhosts_dir: String,
+}
+
+
+impl HostingLayout for Plesk {
+    fn name(&self) -> &'static str {
+        "plesk"
+    }
+
+    fn docroots(&self, user_name: &str) -> Vec<Docroot> {
+        let uid = match get_user_by_name(user_name) {
+            Some(user) => user.uid(),
+            None => return vec!(),
+        };
+        let vhosts_dir = self.vhosts_dir.trim_right_matches('/').to_string();
+        subdirs_of(&vhosts_dir)
+            .into_iter()
+            .filter(|domain| !domain.starts_with("."))
+            .map(|domain| Docroot {
+                path: format!("{}/{}/httpdocs", vhosts_dir, domain),
+                domain: domain,
+            })
+            .filter(|docroot| {
+                match Path::new(&docroot.path).metadata() {
+                    Ok(metadata) => metadata.is_dir() && metadata.uid() == uid,
+                    Err(_) => false,
+                }
+            })
+            .collect()
+    }
+}
+
+
+/*
+    Custom layout defined by path pattern, f.e.: "/srv/www/{user}/{domain}/htdocs"
+    "{user}" is replaced with user name, "{domain}" matches any dir and names domain,
+    "*" matches any dir.
+ */
+pub struct CustomLayout {
+    pub pattern: String,
+}
+
+
+fn expand_pattern(base: String, segments: &[&str], domain: Option<String>, results: &mut Vec<Docroot>) {
+    match segments.first() {
+        None => {
+            match domain {
+                Some(domain) => {
+                    if Path::new(&base).is_dir() {
+                        results.push(Docroot { domain: domain, path: base })
+                    }
+                },
+                None => warn!("Custom layout pattern has no {{domain}} segment, path: {} skipped", base),
+            }
+        },
+        Some(&"{domain}") => {
+            for name in subdirs_of(&base) {
+                expand_pattern(format!("{}/{}", base, name), &segments[1..], Some(name.clone()), results);
+            }
+        },
+        Some(&"*") => {
+            for name in subdirs_of(&base) {
+                expand_pattern(format!("{}/{}", base, name), &segments[1..], domain.clone(), results);
+            }
+        },
+        Some(segment) => {
+            expand_pattern(format!("{}/{}", base, segment), &segments[1..], domain, results);
+        },
+    }
+}
+
+
+impl HostingLayout for CustomLayout {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    fn docroots(&self, user_name: &str) -> Vec<Docroot> {
+        let pattern = self.pattern.replace("{user}", user_name);
+        let segments: Vec<&str> = pattern.split('/').filter(|e| !e.is_empty()).collect();
+        let mut results = vec!();
+        expand_pattern(String::new(), &segments, None, &mut results);
+        results
+    }
+}
+
+
+/* document roots of other domains located inside given one, f.e. cPanel addon domains under public_html */
+pub fn nested_docroots(docroot: &Docroot, docroots: &[Docroot]) -> Vec<String> {
+    docroots
+        .iter()
+        .filter(|other| other.path != docroot.path && Path::new(&other.path).starts_with(&docroot.path))
+        .map(|other| other.path.clone())
+        .collect()
+}
+
+
+/* hosting layout selected in configuration */
+pub fn hosting_layout(config: &Config) -> Result<Box<HostingLayout>, String> {
+    match config.layout.as_str() {
+        "directadmin" => Ok(Box::new(DirectAdmin { home_dir: config.home_dir.clone() })),
+        "cpanel" => Ok(Box::new(CPanel { home_dir: config.home_dir.clone(), userdata_dir: config.cpanel_userdata_dir.clone() })),
+        "plesk" => Ok(Box::new(Plesk { vhosts_dir: config.plesk_vhosts_dir.clone() })),
+        "custom" => {
+            if config.layout_pattern.contains("{domain}") {
+                Ok(Box::new(CustomLayout { pattern: config.layout_pattern.clone() }))
+            } else {
+                Err(format!("Custom layout pattern: '{}' should contain {{domain}} segment", config.layout_pattern))
+            }
+        },
+        unknown => Err(format!("Unknown hosting layout: '{}'. Known layouts: directadmin, cpanel, plesk, custom", unknown)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use process::*;
+
+
+    fn create_dirs(root: &str, dirs: Vec<&str>) {
+        remove_dir_all(Path::new(root)).unwrap_or(());
+        for dir in dirs {
+            create_dir_all(format!("{}/{}", root, dir)).unwrap();
+        }
+    }
+
+
+    #[test]
+    fn directadmin_layout_test() {
+        let root = "/tmp/yak-layouts/directadmin";
+        create_dirs(root, vec!(
+            "admin6/domains/domena.pl/public_html",
+            "admin6/domains/domain.tld.my.pl/public_html",
+            "admin6/domains/sharedip/public_html",
+            "admin6/domains/suspended/public_html",
+            "admin6/domains/no-docroot.pl/private_html",
+        ));
+        let layout = DirectAdmin { home_dir: format!("{}/{{user}}/", root) };
+        let docroots = layout.docroots("admin6");
+        assert!(docroots == vec!(
+            Docroot { domain: String::from("domain.tld.my.pl"), path: format!("{}/admin6/domains/domain.tld.my.pl/public_html", root) },
+            Docroot { domain: String::from("domena.pl"), path: format!("{}/admin6/domains/domena.pl/public_html", root) },
+        ), format!("Docroots: {:?}", docroots));
+        assert!(layout.docroots("nobody").is_empty());
+    }
+
+
+    #[test]
+    fn custom_layout_test() {
+        let root = "/tmp/yak-layouts/custom";
+        create_dirs(root, vec!(
+            "admin6/domena.pl/htdocs",
+            "admin6/other.pl/htdocs",
+            "admin6/other.pl/logs",
+            "admin6/empty.pl/logs",
+        ));
+        let layout = CustomLayout { pattern: format!("{}/{{user}}/{{domain}}/htdocs", root) };
+        let domains: Vec<String> = layout.docroots("admin6").into_iter().map(|e| e.domain).collect();
+        assert!(domains == vec!(String::from("domena.pl"), String::from("other.pl")), format!("Domains: {:?}", domains));
+
+        let mut config = Config::default();
+        config.layout = String::from("custom");
+        assert!(hosting_layout(&config).is_err());
+        config.layout = String::from("nonexistent");
+        assert!(hosting_layout(&config).is_err());
+    }
+
+
+    #[test]
+    fn parse_cpanel_userdata_test() {
+        let main = "---\naddon_domains:\n  addon.pl: addon.main.pl\nmain_domain: main.pl\nparked_domains: []\nsub_domains:\n  - addon.main.pl\n  - blog.main.pl\n";
+        let userdata = parse_cpanel_userdata(main);
+        assert!(userdata.main_domain == "main.pl");
+        assert!(userdata.addon_domains == vec!((String::from("addon.pl"), String::from("addon.main.pl"))));
+        assert!(userdata.sub_domains == vec!(String::from("addon.main.pl"), String::from("blog.main.pl")));
+    }
+
+
+    #[test]
+    fn nested_docroots_test() {
+        let docroot = |domain: &str, path: &str| Docroot { domain: domain.to_string(), path: path.to_string() };
+        let docroots = vec!(
+            docroot("main.pl", "/home/admin6/public_html"),
+            docroot("addon.pl", "/home/admin6/public_html/addon.pl"),
+            docroot("blog.main.pl", "/home/admin6/public_html/blog"),
+            docroot("other.pl", "/home/admin6/public_html_other"),
+        );
+        assert!(nested_docroots(&docroots[0], &docroots) == vec!(String::from("/home/admin6/public_html/addon.pl"), String::from("/home/admin6/public_html/blog")));
+        assert!(nested_docroots(&docroots[1], &docroots).is_empty());
+        assert!(nested_docroots(&docroots[3], &docroots).is_empty());
+    }
+}
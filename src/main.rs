@@ -20,6 +20,7 @@ extern crate curl;
 extern crate rand;
 extern crate ammonia;
 extern crate sha1;
+extern crate openssl;
 extern crate term;
 extern crate difference;
 extern crate flate2;
@@ -27,6 +28,8 @@ extern crate bincode;
 extern crate rustc_serialize;
 extern crate rayon;
 extern crate unicase;
+#[cfg(feature = "query")]
+extern crate rusqlite;
 // extern crate flame;
 // extern crate rsgenetic;
 
@@ -35,11 +38,37 @@ extern crate unicase;
 mod structs;
 mod utils;
 mod base;
+mod config;
 mod process;
 mod api_server;
+mod cli;
+mod layouts;
+mod webapps;
+mod spice;
+mod integrity;
+mod signatures;
+mod heuristics;
+mod states;
+mod probe;
+mod yara;
+mod delta;
+mod blobs;
+mod retention;
+mod format;
+mod store;
+mod index;
+mod query;
 
 use process::*;
-use api_server::start;
+use cli::*;
+use layouts::*;
+use webapps::*;
+use spice::load_baseline;
+use integrity::verify_integrity;
+use states::classify_domain;
+use probe::probe_domain;
+use delta::{current_state, delta_of, apply_delta};
+use query::update_query_store;
 
 use rayon::prelude::*;
 use std::sync::Arc;
@@ -49,75 +78,219 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 fn main() {
     env_logger::init().unwrap();
 
-    for arg in env::args() {
-        match arg.as_str() {
-            "api" | "www" | "web" | "server" | "s" => {
-                info!("Starting Http service on port: {}", root_default_http_port());
-                api_server::start();
-            },
-
-            _ => {},
-        }
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let loaded = match extract_config_option(&mut args) {
+        Ok(config_file) => load_config(config_file),
+        Err(err) => Err(err),
+    };
+    match loaded {
+        Ok(config) => set_config(config),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(EXIT_USAGE)
+        },
     }
 
-    info!("Traversing home dirs..");
-    main_traverser()
+    let exit_code = match parse_arguments(&args) {
+        Ok(Command::Scan(users, severity, full)) => {
+            match severity {
+                Some(level) => {
+                    let mut scan_config = config();
+                    scan_config.severity = level;
+                    set_config(scan_config);
+                },
+                None => {},
+            }
+            info!("Traversing home dirs with severity level: {:?}..", config().severity);
+            main_traverser(users, full)
+        },
+
+        Ok(Command::Serve) => {
+            info!("Starting Http service on port: {}", config().http_port);
+            api_server::start();
+            EXIT_OK
+        },
+
+        Ok(Command::History(user, json_output, since, until)) => history_command(user, json_output, since, until),
+        Ok(Command::Show(user, uuid, delta_only)) => show_command(user, uuid, delta_only),
+        Ok(Command::Diff(user, uuid1, uuid2)) => diff_command(user, uuid1, uuid2),
+        Ok(Command::Verify(users)) => verify_command(users),
+        Ok(Command::Domains(app_type, outdated_only, users)) => domains_command(app_type, outdated_only, users),
+        Ok(Command::Spice(app_type, version, release_dir, interpreter, supported)) => spice_command(app_type, version, release_dir, interpreter, supported),
+        Ok(Command::Threats(users)) => threats_command(users),
+        Ok(Command::Gc) => gc_command(),
+        Ok(Command::Prune(users)) => prune_command(users),
+        Ok(Command::State(user, domain)) => state_command(user, domain),
+        Ok(Command::Reindex(users)) => reindex_command(users),
+        Ok(Command::Query(sql, rebuild)) => query_command(sql, rebuild),
+
+        Ok(Command::Help) => {
+            println!("{}", usage());
+            EXIT_OK
+        },
+
+        Err(err) => {
+            error!("{}", err);
+            println!("{}", usage());
+            EXIT_USAGE
+        },
+    };
+    std::process::exit(exit_code)
 }
 
 
-fn main_traverser() {
+/* users - names of users to traverse, empty means: all users. full - read all files, even unchanged ones */
+fn main_traverser(users: Vec<String>, full: bool) -> i32 {
     let start = precise_time_ns();
     let files_processed: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
     let files_skipped: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+    let files_unchanged: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+    let store_failures: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
 
     // let _ = rayon::Configuration::new().set_num_threads(4);
 
-    fetch_users().par_iter_mut().for_each(
+    let mut system_users = fetch_users();
+    if !users.is_empty() {
+        system_users.retain(|user| users.contains(&user.name().to_string()));
+        if system_users.is_empty() {
+            error!("None of given users: {:?} exist in the system", users);
+            return EXIT_NOT_FOUND
+        }
+    }
+
+    let layout = match hosting_layout(&config()) {
+        Ok(layout) => layout,
+        Err(err) => {
+            error!("{}", err);
+            return EXIT_FAILURE
+        },
+    };
+    info!("Using hosting layout: {}", layout.name());
+    let releases = load_releases(&config().releases_file);
+
+    system_users.par_iter_mut().for_each(
         |user| {
-            let path = format!("/home/{}/", user.name());
-            if Path::new(path.as_str()).exists() {
+            let docroots = layout.docroots(user.name());
+            if !docroots.is_empty() {
+                import_legacy_changesets(user.name());
+                let previous = current_state(user.name().to_string());
+                let previous_files = if full {
+                    HashMap::new()
+                } else {
+                    files_by_path(&previous)
+                };
                 let mut changeset = Changeset {
                     uuid: Uuid::new_v4(),
-                    parent: root_uuid(), // XXX - should be attached to "root branch"
+                    parent: root_uuid(), /* replaced by uuid of previous changeset, when stored as delta */
                     timestamp: time::precise_time_ns() / 1000 / 1000,
+                    severity: config().severity,
+                    full: true,
+                    removed: vec!(),
                     entries: Vec::new(),
                 };
 
-                info!("Traversing path: '{}'", path);
-                let walker = WalkDir::new(path)
-                    .follow_links(false)
-                    .max_depth(4)
-                    .max_open(512)
-                    .into_iter();
-
-                for entry in walker /* filter everything we don't have access to */
-                                .filter_map(|e| e.ok())
-                                .filter(|e| e.metadata().unwrap().is_file() && e.path().to_str().unwrap_or("").contains("domains")) {
-
-                    // let entry_name = format!("path: {}", entry.path().to_str().unwrap_or("NO-FILE"));
-                    // flame::start(entry_name.clone());
-
-                    match process_domain(entry.path()) {
-                        Some(domain_entry) => {
-                            /* write flamegraph */
-                            // flame::end(entry_name.clone());
-                            // let graph_file_name = format!("{}-{}.svg", user.name(), domain_entry.name);
-                            // match flame::dump_svg(&mut File::create(graph_file_name).unwrap()) {
-                            //     Ok(_) => debug!("Graph stored successfully"),
-                            //     Err(err) => warn!("Failed to store graph: {}", err),
-                            // }
-                            // flame::clear();
-
-                            changeset.entries.push(domain_entry);
-
-                            let value = files_processed.load(Ordering::SeqCst);
-                            files_processed.store(value + 1, Ordering::SeqCst);
-                        },
-                        None => {
-                            let value = files_skipped.load(Ordering::SeqCst);
-                            files_skipped.store(value + 1, Ordering::SeqCst);
-                        },
+                let nested: Vec<Vec<String>> = docroots.iter().map(|docroot| nested_docroots(docroot, &docroots)).collect();
+                for (docroot, nested) in docroots.into_iter().zip(nested.into_iter()) {
+                    info!("Traversing domain: {}, path: '{}'", docroot.domain, docroot.path);
+                    let app_type = detect_webapp(&docroot.path);
+                    let version = match app_type {
+                        Some(ref detected) => detect_version(detected, &docroot.path).unwrap_or(String::new()),
+                        None => String::new(),
+                    };
+                    let (outdated, zombie) = match app_type {
+                        Some(ref detected) if !version.is_empty() => check_release(detected, &version, &releases),
+                        _ => (false, false),
+                    };
+                    let baseline = match app_type {
+                        Some(ref detected) if !version.is_empty() => load_baseline(&config().spice_dir, detected, &version),
+                        _ => None,
+                    };
+                    let fs_diffs = match baseline {
+                        Some(ref baseline) => verify_integrity(&docroot.path, baseline, &previous_files),
+                        None => vec!(),
+                    };
+                    if !fs_diffs.is_empty() {
+                        warn!("Domain: {} has {} framework files that differ from pristine: {:?} {}", docroot.domain, fs_diffs.len(), app_type, version);
                     }
+                    let mut domain_entry = DomainEntry {
+                        name: docroot.domain.clone(),
+                        docroot: docroot.path.clone(),
+                        app_type: app_type,
+                        version: version,
+                        interpreter: baseline.map(|baseline| baseline.interpreter).unwrap_or(String::new()),
+                        outdated: outdated,
+                        zombie: zombie,
+                        fs_diffs: fs_diffs,
+                        .. Default::default()
+                    };
+                    let walker = WalkDir::new(docroot.path.clone())
+                        .follow_links(false)
+                        .max_depth(config().max_depth.saturating_sub(DOCROOT_DEPTH))
+                        .max_open(512)
+                        .into_iter();
+
+                    for entry in walker /* filter everything we don't have access to */
+                                    .filter_map(|e| e.ok())
+                                    .filter(|e| !nested.iter().any(|dir| e.path().starts_with(dir))) /* scanned as separate domains */
+                                    .filter(|e| e.metadata().map(|m| m.is_file()).unwrap_or(false)) {
+
+                        let unchanged = match (entry.path().to_str().and_then(|path| previous_files.get(path)), entry.metadata()) {
+                            (Some(previous), Ok(ref metadata)) if unchanged_file(previous, metadata) => Some(previous.clone()),
+                            _ => None,
+                        };
+                        match unchanged {
+                            Some(mut file_entry) => {
+                                file_entry.modified = get_time().sec - file_entry.mtime;
+                                domain_entry.files.push(file_entry);
+                                let value = files_unchanged.load(Ordering::SeqCst);
+                                files_unchanged.store(value + 1, Ordering::SeqCst);
+                                continue
+                            },
+                            None => {},
+                        }
+
+                        // let entry_name = format!("path: {}", entry.path().to_str().unwrap_or("NO-FILE"));
+                        // flame::start(entry_name.clone());
+
+                        match process_path(entry.path()) {
+                            Some(file_entry) => {
+                                /* write flamegraph */
+                                // flame::end(entry_name.clone());
+                                // let graph_file_name = format!("{}-{}.svg", user.name(), domain_entry.name);
+                                // match flame::dump_svg(&mut File::create(graph_file_name).unwrap()) {
+                                //     Ok(_) => debug!("Graph stored successfully"),
+                                //     Err(err) => warn!("Failed to store graph: {}", err),
+                                // }
+                                // flame::clear();
+
+                                domain_entry.files.push(file_entry);
+
+                                let value = files_processed.load(Ordering::SeqCst);
+                                files_processed.store(value + 1, Ordering::SeqCst);
+                            },
+                            None => {
+                                let value = files_skipped.load(Ordering::SeqCst);
+                                files_skipped.store(value + 1, Ordering::SeqCst);
+                            },
+                        }
+                    }
+
+                    probe_domain(&mut domain_entry);
+                    let threshold = config().suspicion_threshold;
+                    domain_entry.fs_suspicious = domain_entry.files
+                        .iter()
+                        .filter(|file| is_suspicious(file, threshold))
+                        .map(|file| file.path.clone())
+                        .collect();
+                    for file in domain_entry.files.iter() {
+                        if !domain_entry.encodings.contains(&file.encoding) {
+                            domain_entry.encodings.push(file.encoding.clone());
+                        }
+                    }
+                    domain_entry.encodings.sort();
+                    domain_entry.states = classify_domain(&domain_entry, threshold, &changeset.severity);
+                    info!("Domain: {} files: {} states: {:?}", domain_entry.name, domain_entry.files.len(), domain_entry.states);
+                    changeset.entries.push(domain_entry);
                 }
 
                 // /* write changeset serialized to json */
@@ -125,15 +298,50 @@ fn main_traverser() {
                 // info!("Changeset(json) stored: {} ({} bytes)", file_name, bytes_written);
 
                 /* now write compressed binary changeset */
-                let (file_name, bytes_written) = store_changeset(user.name().to_string(), changeset);
-                info!("Changeset stored: {} ({} bytes)", file_name, bytes_written);
+                trim_changeset(&mut changeset);
+                /* first changeset of user (or one after broken chain) is stored as full snapshot */
+                let changeset = if previous.parent == root_invalid_uuid() {
+                    changeset
+                } else {
+                    delta_of(&previous, changeset)
+                };
+                let uuid = changeset.uuid;
+                let state = apply_delta(previous, &changeset);
+                match store_changeset(user.name().to_string(), changeset) {
+                    Ok((user_dir, bytes_written)) => {
+                        info!("Changeset: {} stored in: {} ({} bytes)", uuid, user_dir, bytes_written);
+                        match SpiceStore::local().store_domain_states(user.name(), &state) {
+                            Ok(domains) => {
+                                debug!("Current state of: {} domains of user: {} updated", domains, user.name());
+                                match update_query_store(&config().host_name(), user.name(), &state) {
+                                    Ok(_) => {},
+                                    Err(err) => warn!("{}. Run: yak query --rebuild", err), /* changesets stay the source of truth */
+                                }
+                            },
+                            Err(err) => {
+                                error!("{}", err);
+                                let value = store_failures.load(Ordering::SeqCst);
+                                store_failures.store(value + 1, Ordering::SeqCst);
+                            },
+                        }
+                    },
+                    Err(err) => {
+                        error!("{}", err);
+                        let value = store_failures.load(Ordering::SeqCst);
+                        store_failures.store(value + 1, Ordering::SeqCst);
+                    },
+                }
             }
         }
     );
 
     let end = precise_time_ns();
-    info!("Traverse for: {} files, (skipped: {} files), elapsed: {} miliseconds", files_processed.load(Ordering::SeqCst), files_skipped.load(Ordering::SeqCst), (end - start) / 1000 / 1000);
-
+    info!("Traverse for: {} files, (skipped: {} files, unchanged: {} files), elapsed: {} miliseconds", files_processed.load(Ordering::SeqCst), files_skipped.load(Ordering::SeqCst), files_unchanged.load(Ordering::SeqCst), (end - start) / 1000 / 1000);
+    if store_failures.load(Ordering::SeqCst) > 0 {
+        error!("Failed to store changesets of: {} users", store_failures.load(Ordering::SeqCst));
+        return EXIT_FAILURE
+    }
+    EXIT_OK
 }
 
 
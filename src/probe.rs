@@ -0,0 +1,141 @@
+use process::*;
+
+use std::sync::Mutex;
+use std::collections::HashMap;
+
+
+/* result of single HTTP(S) request */
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub struct HttpProbe {
+    pub url: String,
+    pub status_code: u32,
+    pub content: String,
+    pub content_encoding: String,
+    pub content_size: usize,
+    pub response_time: u64,
+}
+
+
+lazy_static! {
+    /* probes made in current run, keyed by url. Same domain might be served from multiple document roots */
+    static ref PROBES: Mutex<HashMap<String, HttpProbe>> = Mutex::new(HashMap::new());
+}
+
+
+fn request(url: &str) -> HttpProbe {
+    let start = precise_time_ns();
+    let config = config();
+    let mut probe = HttpProbe {
+        url: url.to_string(),
+        status_code: 0,
+        content: String::new(),
+        content_encoding: String::new(), /* XXX */
+        content_size: 0,
+        response_time: 0,
+    };
+    match http::handle()
+        .follow_location(0)
+        .timeout(config.timeout)
+        .connect_timeout(config.connection_timeout)
+        .ssl_verifypeer(false)
+        .get(url)
+        .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_11_4) AppleWebKit/601.5.17 (KHTML, like Gecko) Version/9.1 Safari/601.5.17")
+        .exec() {
+        Ok(resp) => {
+            let end = precise_time_ns();
+            debug!("Processed external request: {} in {}ms", url, (end - start) / 1000 / 1000);
+            probe.content = strip_html_tags_slice(resp.get_body());
+            probe.content_size = probe.content.len();
+            probe.status_code = resp.get_code();
+            probe.response_time = (end - start) / 1000 / 1000;
+        },
+        Err(err) => {
+            match err.to_string().as_str() {
+                "Couldn't resolve host name" => {
+                    debug!("Host resolve problem: {:?}, for: {}", err, url);
+                    probe.status_code = 410; /* http "gone" error - for unresolvable domain */
+                },
+                _ => {
+                    debug!("Host problem: {:?}, for: {} (404 fallback)", err, url);
+                    probe.status_code = 404;
+                },
+            }
+        },
+    }
+    probe
+}
+
+
+/* requests given url only if it wasn't probed before in current run */
+pub fn probe_with<F>(url: &str, request: F) -> HttpProbe where F: Fn(&str) -> HttpProbe {
+    match PROBES.lock().unwrap().get(url) {
+        Some(cached) => {
+            debug!("Cached probe of: {}", url);
+            return cached.clone()
+        },
+        None => {},
+    }
+    let probe = request(url); /* lock isn't held during request */
+    PROBES.lock().unwrap().insert(url.to_string(), probe.clone());
+    probe
+}
+
+
+pub fn probe_url(url: &str) -> HttpProbe {
+    probe_with(url, request)
+}
+
+
+/* probes index of domain and each path from config, once per protocol */
+pub fn probe_domain(domain_entry: &mut DomainEntry) {
+    for protocol in vec!("http", "https") {
+        let index = probe_url(&format!("{}://{}/", protocol, domain_entry.name));
+        match protocol {
+            "http" => {
+                domain_entry.http_content_encoding = index.content_encoding;
+                domain_entry.http_content = index.content;
+                domain_entry.http_content_size = index.content_size;
+                domain_entry.http_status_code = index.status_code;
+                domain_entry.http_response_time = index.response_time;
+            },
+            _ => {
+                domain_entry.https_content_encoding = index.content_encoding;
+                domain_entry.https_content = index.content;
+                domain_entry.https_content_size = index.content_size;
+                domain_entry.https_status_code = index.status_code;
+                domain_entry.https_response_time = index.response_time;
+            },
+        }
+        for path in config().probe_paths {
+            let url = format!("{}://{}/{}", protocol, domain_entry.name, path.trim_left_matches('/'));
+            domain_entry.probes.push(probe_url(&url));
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn probe_with_test() {
+    use std::cell::Cell;
+
+    let requests = Cell::new(0);
+    let fake_request = |url: &str| {
+        requests.set(requests.get() + 1);
+        HttpProbe {
+            url: url.to_string(),
+            status_code: 200,
+            content: String::from("Hello"),
+            content_encoding: String::new(),
+            content_size: 5,
+            response_time: 1,
+        }
+    };
+    let first = probe_with("http://probe-test.example.com/", &fake_request);
+    let second = probe_with("http://probe-test.example.com/", &fake_request);
+    assert!(first == second);
+    assert!(requests.get() == 1);
+
+    probe_with("https://probe-test.example.com/", &fake_request);
+    assert!(requests.get() == 2);
+}
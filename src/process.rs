@@ -1,4 +1,11 @@
 pub use base::*;
+pub use config::*;
+pub use layouts::Docroot;
+pub use signatures::{scan_signatures, scan_head_and_tail};
+pub use heuristics::{suspicion_of, is_suspicious};
+pub use blobs::{store_blob, content_of};
+pub use format::{encode_changeset, decode_changeset, migrate_changeset};
+pub use store::SpiceStore;
 pub use utils::*;
 pub use structs::*;
 
@@ -7,8 +14,9 @@ pub use uuid::Uuid;
 pub use regex::Regex;
 pub use std::path::Path;
 pub use time::{get_time, precise_time_ns};
-pub use std::io::{BufReader, BufWriter};
-pub use std::fs::{remove_dir_all, create_dir_all, File, OpenOptions};
+pub use std::io::{BufReader, BufWriter, Seek, SeekFrom};
+pub use std::fs::{remove_dir_all, remove_file, rename, create_dir_all, File, OpenOptions, Metadata};
+pub use std::collections::HashMap;
 pub use std::io::prelude::{Read,Write};
 
 pub use curl::http;
@@ -27,7 +35,7 @@ pub use bincode::rustc_serialize::{encode, decode_from};
 
 
 pub fn store_changeset_json(user_name: String, changeset: Changeset) -> (String, usize) {
-    let changeset_dir = format!(".changesets/{}", user_name);
+    let changeset_dir = SpiceStore::local().user_dir(&user_name);
     match create_dir_all(changeset_dir.clone()) {
         Ok(_) => {},
         Err(err) => error!("{:?}", err),
@@ -60,79 +68,73 @@ pub fn store_changeset_json(user_name: String, changeset: Changeset) -> (String,
 }
 
 
-pub fn store_changeset(user_name: String, changeset: Changeset) -> (String, usize) {
-    let changeset_dir = format!(".changesets/{}", user_name);
-    match create_dir_all(changeset_dir.clone()) {
-        Ok(_) => {},
-        Err(err) => error!("{:?}", err),
+/* only Psycho severity level keeps bodies of HTTP responses, states are classified using their sizes */
+pub fn trim_changeset(changeset: &mut Changeset) {
+    if changeset.severity == SeverityLevels::Psycho {
+        return
+    }
+    for entry in changeset.entries.iter_mut() {
+        entry.http_content = String::new();
+        entry.https_content = String::new();
+        for probe in entry.probes.iter_mut() {
+            probe.content = String::new();
+        }
     }
-    let file_name = format!("{}/{}-{}.chgset", changeset_dir, changeset.uuid, changeset.timestamp);
-    let binary_encoded = encode(&changeset, SizeLimit::Infinite).unwrap();
+}
 
-    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::Best);
-    zlib.write(&binary_encoded[..]).unwrap();
-    let compressed_bytes = zlib.finish().unwrap();
 
-    let mut writer = BufWriter::new(File::create(file_name.clone()).unwrap());
-    let bytes_written = writer.write(&compressed_bytes).unwrap();
-    (file_name.to_string(), bytes_written)
+pub fn store_changeset(user_name: String, changeset: Changeset) -> Result<(String, usize), String> {
+    SpiceStore::local().store_changeset(&user_name, &changeset)
 }
 
 
-pub fn all_changesets(user_name: String) -> Vec<Changeset> {
-    let changeset_dir = format!(".changesets/{}", user_name);
-    match create_dir_all(changeset_dir.clone()) {
-        Ok(_) => {},
-        Err(err) => error!("{:?}", err),
-    }
-    let mut changesets = vec!();
-    info!("Reading changesets from dir: {}", changeset_dir);
-    let walker = WalkDir::new(changeset_dir)
-        .follow_links(false)
-        .max_depth(2)
-        .max_open(256)
-        .into_iter();
-
-    for entry in walker
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().to_str().unwrap_or("").ends_with(".chgset")) {
-
-        match File::open(entry.path()) {
-            Ok(file) => {
-                let reader = BufReader::new(file);
-                let mut decoder = ZlibDecoder::new(reader);
-                let changeset: Changeset = match decode_from(&mut decoder, SizeLimit::Infinite) {
-                    Ok(r) => r,
-                    Err(err) => {
-                        let e = entry.path().to_str().unwrap_or("NO-FILE");
-                        warn!("Data processing failure: {}, while processing changeset: {}", err, e);
-                        invalid_changeset()
-                    },
-                };
-                changesets.push(changeset.clone());
-                debug!("Decoded Changeset: {}", changeset)
-            },
-            Err(err) => {
-                error!("Failed to open file: {}. Error: {}", entry.path().to_str().unwrap_or("NO-FILE"), err);
-            },
-        }
-    }
-    changesets.sort_by(|a, b| a.timestamp.cmp(&b.timestamp)); /* sort changesets by timestamp */
-    changesets
+pub fn all_changesets(user_name: String) -> Result<Vec<Changeset>, String> {
+    SpiceStore::local().changesets(&user_name)
+}
+
+
+pub fn remove_changeset(user_name: &str, changeset: &Changeset) -> Result<(), String> {
+    SpiceStore::local().remove_changeset(user_name, changeset)
+}
+
+
+pub fn quarantined_changesets(user_name: &str) -> Vec<String> {
+    SpiceStore::local().quarantined_changesets(user_name)
 }
 
 
 pub fn mostrecent_changeset(user_name: String) -> Changeset {
-    match all_changesets(user_name).pop() {
+    match all_changesets(user_name).ok().and_then(|mut changesets| changesets.pop()) {
         Some(value) => value,
         None => invalid_changeset(),
     }
 }
 
 
+pub fn find_changeset(user_name: String, uuid: Uuid) -> Option<Changeset> {
+    SpiceStore::local().changeset(&user_name, uuid)
+}
+
+
+/* changesets of first releases, kept in legacy dir, are moved to SpiceStore of this host once */
+pub fn import_legacy_changesets(user_name: &str) {
+    let config = config();
+    match SpiceStore::local().import_legacy(&config.changesets_dir, &config.blobs_dir, user_name) {
+        Ok(_) => {},
+        Err(err) => error!("Failed to import legacy changesets of user: {}. Cause: {}", user_name, err),
+    }
+}
+
+
+/* names of all users that have any changesets stored on this host */
+pub fn changeset_users() -> Vec<String> {
+    SpiceStore::local().users()
+}
+
+
 pub fn process_file(abs_path: &str, f: &File) -> Result<FileEntry, String> {
     if valid_file_extensions(abs_path) {
-        let bytes_to_read = 131070u64; /* 128KiB */
+        let bytes_to_read = config().read_limit;
         let metadata = match f.metadata() {
             Ok(some) => some,
             Err(err) => return Err(format!("Failed to read metadata of path: {}. Cause: {}", abs_path, err)),
@@ -141,6 +143,11 @@ pub fn process_file(abs_path: &str, f: &File) -> Result<FileEntry, String> {
 
         match read_fragment(&mut reader, bytes_to_read) {
             Some(binary_content) => {
+                /* hash whole file, not only the fragment read above */
+                let (raw_sha1, raw_sha256) = match raw_checksums_of((&binary_content[..]).chain(&mut reader)) {
+                    Ok(checksums) => checksums,
+                    Err(err) => return Err(format!("Error reading file: '{}'. Cause: {}", abs_path, err)),
+                };
                 let sys_pw = match get_user_by_uid(metadata.uid()) {
                     Some(user) => user,
                     None => get_user_by_uid(0).unwrap(), /* this user must exists */
@@ -152,39 +159,73 @@ pub fn process_file(abs_path: &str, f: &File) -> Result<FileEntry, String> {
                     uid: metadata.uid(),
                     gid: metadata.gid()
                 };
+                /* bigger files are scanned by head and tail of read_limit bytes, matches in the middle are missed */
+                let head_bytes = binary_content.len() as u64;
+                let tail_offset = if metadata.size() > head_bytes + bytes_to_read {
+                    metadata.size() - bytes_to_read
+                } else {
+                    head_bytes
+                };
+                let tail = if metadata.size() > head_bytes {
+                    match reader.seek(SeekFrom::Start(tail_offset)).ok().and_then(|_| read_fragment(&mut reader, bytes_to_read)) {
+                        Some(tail) => tail,
+                        None => return Err(format!("Error reading tail of file: '{}'", abs_path)),
+                    }
+                } else {
+                    vec!()
+                };
+                let mut scanned_content = binary_content.clone();
+                scanned_content.extend_from_slice(&tail);
                 let buf = strip_html_tags(&binary_content);
+                /* stored content is bounded to read_limit, whole file is only hashed */
+                let content_sha256 = match store_blob(&config().blobs_dir, &binary_content) {
+                    Ok(sha256) => sha256,
+                    Err(err) => {
+                        warn!("Content of file: {} not stored. {}", abs_path, err);
+                        String::new()
+                    },
+                };
                 let mut entry = FileEntry {
                     owner: an_owner,
                     path: abs_path.to_string(),
-                    /*
-                        http://ół.pl/01ba0ee942dc3aefadcab35ebd5c9268.png
-                        cut off all non printable control characters: */
-                    local_content: binary_content.clone().into_iter().filter(|e| *e > 13).collect::<Vec<u8>>(),
+                    content_sha256: content_sha256,
+                    raw_sha1: raw_sha1,
+                    raw_sha256: raw_sha256,
+                    signature_hits: scan_head_and_tail(&binary_content, &tail, tail_offset as usize, metadata.size()),
+                    suspicion: suspicion_of(abs_path, &scanned_content),
                     size: metadata.size(),
                     mode: metadata.mode() as u32,
                     modified: get_time().sec - metadata.mtime(),
+                    mtime: metadata.mtime(),
+                    inode: metadata.ino(),
                     .. Default::default()
                 };
+                for hit in entry.signature_hits.iter() {
+                    warn!("Signature: {} ({:?}) matched in file: {} at offset: {}", hit.name, hit.severity, abs_path, hit.offset);
+                }
+                if entry.suspicion >= config().suspicion_threshold {
+                    warn!("Suspicious file: {} with heuristic score: {}", abs_path, entry.suspicion);
+                }
                 match detect_encoding(&binary_content) {
                     Some(enc) => {
                         entry.encoding = enc.name().to_string();
                         match detect_language(&buf, Format::Text) {
                             (Some(Lang(lang)), Reliable) => {
-                                entry.sha1 = sha1_of(buf);
+                                entry.text_sha1 = sha1_of(buf);
                                 entry.lang = String::from(lang);
                                 debug!("Reliable detection: {}", json::encode(&entry).unwrap());
                                 Ok(entry)
                             },
 
                             (Some(Lang(lang)), _) => {
-                                entry.sha1 = sha1_of(buf);
+                                entry.text_sha1 = sha1_of(buf);
                                 entry.lang = String::from(lang);
                                 debug!("Unreliable detection: {}", entry.to_string());
                                 Ok(entry)
                             },
 
                             (None, _) => { /* not detected properly or value isn't reliable enough to tell */
-                                entry.sha1 = sha1_of(buf);
+                                entry.text_sha1 = sha1_of(buf);
                                 entry.lang = String::from("en");
                                 debug!("No detection for: {}. Doing fallback to 'en'", entry.to_string());
                                 Ok(entry)
@@ -193,7 +234,7 @@ pub fn process_file(abs_path: &str, f: &File) -> Result<FileEntry, String> {
                     },
 
                     None => {
-                        entry.sha1 = sha1_of(buf);
+                        entry.text_sha1 = sha1_of(buf);
                         entry.encoding = "ASCII".to_string();
                         entry.lang = "en".to_string();
                         Ok(entry)
@@ -210,7 +251,26 @@ pub fn process_file(abs_path: &str, f: &File) -> Result<FileEntry, String> {
 }
 
 
-pub fn process_domain(path: &Path) -> Option<DomainEntry> {
+/* files of given changeset by path, used to skip unchanged files in incremental scan */
+pub fn files_by_path(changeset: &Changeset) -> HashMap<String, FileEntry> {
+    let mut files = HashMap::new();
+    for domain in changeset.entries.iter() {
+        for file in domain.files.iter() {
+            files.insert(file.path.clone(), file.clone());
+        }
+    }
+    files
+}
+
+
+/* previously scanned file is unchanged, if its size, modification time and inode are the same */
+pub fn unchanged_file(previous: &FileEntry, metadata: &Metadata) -> bool {
+    previous.size == metadata.size() && previous.mtime == metadata.mtime() && previous.inode == metadata.ino()
+}
+
+
+/* opens and processes single file found in domain document root */
+pub fn process_path(path: &Path) -> Option<FileEntry> {
     let name = match path.to_str() {
         Some(a_path) => a_path,
         None => "",
@@ -218,100 +278,7 @@ pub fn process_domain(path: &Path) -> Option<DomainEntry> {
     match File::open(name) {
         Ok(f) => {
             match process_file(name, &f) {
-                Ok(file_entry) => {
-                    /* default domain location: /home/{owner.name}/domains/{domain.name}/public_html/ */
-                    let domain_from_path = Regex::new(r".*/domains/(.*)/public_html/.*").unwrap();
-                    for _domain in domain_from_path.captures_iter(file_entry.path.as_str()) {
-                        let domain = match _domain.at(1).unwrap() {
-                            "" | "sharedip" | "default" | "suspended" => return None,
-                            dom => dom,
-                        };
-                        let by = format!("{}/public_html", domain);
-                        debug!("Domain detection: {}", domain);
-
-                        let request_path = file_entry.path.split(by.as_str()).last().unwrap_or("/");
-                        let mut result = DomainEntry {
-                            file: file_entry.clone(),
-                            request_path: format!("{}", request_path),
-                            name: String::from(domain),
-                            .. Default::default()
-                        };
-
-                        let request_protocols = vec!("http", "https");
-                        for protocol in request_protocols {
-                            let start = precise_time_ns();
-                            match http::handle()
-                                .follow_location(0)
-                                .timeout(root_default_timeout())
-                                .connect_timeout(root_default_connection_timeout())
-                                .ssl_verifypeer(false)
-                                .get(format!("{}://{}{}", protocol, domain, request_path))
-                                .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_11_4) AppleWebKit/601.5.17 (KHTML, like Gecko) Version/9.1 Safari/601.5.17")
-                                .exec() {
-                                Ok(resp) => {
-                                    let end = precise_time_ns();
-                                    debug!("Processed external request: {}://{}{} in {}ms", protocol, domain, request_path, (end - start) / 1000 / 1000);
-                                    let contents = strip_html_tags_slice(resp.get_body());
-                                    match protocol {
-                                        "http" => {
-                                            result.http_content_encoding = String::new(); /* XXX */
-                                            result.http_content = contents.clone();
-                                            result.http_content_size = contents.len();
-                                            result.http_status_code = resp.get_code();
-                                            result.http_response_time = (end - start) / 1000 / 1000;
-                                        },
-                                        "https" => {
-                                            result.https_content_encoding = String::new(); /* XXX */
-                                            result.https_content = contents.clone();
-                                            result.https_content_size = contents.len();
-                                            result.https_status_code = resp.get_code();
-                                            result.https_response_time = (end - start) / 1000 / 1000;
-                                        },
-                                        _ => {
-                                        }
-                                    }
-                                },
-                                Err(err) => {
-                                    // 2016-03-24 15:00:02 - dmilith - XXX: FIXME: NONDRY: UGLY:
-                                    match protocol {
-                                        "http" => {
-                                            match err.to_string().as_str() {
-                                                "Couldn't resolve host name" => {
-                                                    debug!("{} host resolve problem: {:?}, for: {}", protocol, err, format!("{}://{}{}", protocol, domain, request_path));
-                                                    result.http_content_size = 0;
-                                                    result.http_status_code = 410; /* http "gone" error - for unresolvable domain */
-                                                },
-                                                _ => {
-                                                    debug!("{} host problem: {:?}, for: {} (404 fallback)", protocol, err, format!("{}://{}{}", protocol, domain, request_path));
-                                                    result.http_content_size = 0;
-                                                    result.http_status_code = 404;
-                                                }
-                                            }
-                                        },
-                                        "https" => {
-                                            match err.to_string().as_str() {
-                                                "Couldn't resolve host name" => {
-                                                    debug!("{} host resolve problem: {:?}, for: {}", protocol, err, format!("{}://{}{}", protocol, domain, request_path));
-                                                    result.https_content_size = 0;
-                                                    result.https_status_code = 410; /* http "gone" error - for unresolvable domain */
-                                                },
-                                                _ => {
-                                                    debug!("{} host problem: {:?}, for: {} (404 fallback)", protocol, err, format!("{}://{}{}", protocol, domain, request_path));
-                                                    result.https_content_size = 0;
-                                                    result.https_status_code = 404;
-                                                }
-                                            }
-                                        },
-                                        _ => {
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        return Some(result)
-                    };
-                    None
-                },
+                Ok(file_entry) => Some(file_entry),
                 Err(err) => {
                     if err.as_str().starts_with("Invalid file type") {
                         None /* report nothing */
@@ -471,36 +438,60 @@ mod tests {
     }
 
 
+    #[test]
+    fn unchanged_file_test() {
+        let (root, path) = ("/tmp/yak-unchanged-test", "/tmp/yak-unchanged-test/index.php");
+        remove_dir_all(root).unwrap_or(());
+        create_dir_all(root).unwrap();
+        /* content of processed file is stored as blob */
+        set_config(Config { blobs_dir: format!("{}/blobs", root), .. config() });
+        File::create(path).unwrap().write_all(b"<?php echo 'Hello';").unwrap();
+        let previous = process_file(path, &File::open(path).unwrap()).unwrap();
+        assert!(Path::new(&format!("{}/blobs", root)).exists());
+        let changeset = Changeset {
+            entries: vec!(DomainEntry { files: vec!(previous.clone()), .. Default::default() }),
+            .. Default::default()
+        };
+        assert!(files_by_path(&changeset).get(path).map(|file| file.raw_sha1.clone()) == Some(previous.raw_sha1.clone()));
+        assert!(unchanged_file(&previous, &File::open(path).unwrap().metadata().unwrap()));
+
+        OpenOptions::new().append(true).open(path).unwrap().write_all(b" eval($_POST['x']);").unwrap();
+        assert!(!unchanged_file(&previous, &File::open(path).unwrap().metadata().unwrap()));
+    }
+
+
     #[test]
     fn store_restore_changesets_test() {
+        let blobs_dir = "/tmp/yak-changesets-blobs-test";
+        remove_dir_all(blobs_dir).unwrap_or(());
         let origin_changeset = Changeset {
             uuid: Uuid::new_v4(),
             parent: root_uuid(), // XXX - should be attached to "root branch"
             timestamp: precise_time_ns() / 1000 / 1000,
+            severity: SeverityLevels::Normal,
+            full: true,
+            removed: vec!(),
             entries: vec!(
                 DomainEntry {
-                    file: FileEntry {
-                        path: String::from("/tmp/index.php"),
-                        local_content: "<?php echo INDEX phpinfo();".to_string().into_bytes(),
-                        .. Default::default()
-                    },
-                    request_path: String::from("/index.php"),
-                    name: String::from("index.php"),
-                    .. Default::default()
-                },
-                DomainEntry {
-                    file: FileEntry {
-                        path: String::from("/tmp/main.php"),
-                        local_content: "<?php echo MAIN phpinfo();".to_string().into_bytes(),
-                        owner: Owner {
-                            name: String::from("admin6"),
+                    files: vec!(
+                        FileEntry {
+                            path: String::from("/tmp/index.php"),
+                            content_sha256: store_blob(blobs_dir, "<?php echo INDEX phpinfo();".as_bytes()).unwrap_or(String::new()),
                             .. Default::default()
                         },
-                        .. Default::default()
-                    },
-                    http_content: String::from("<?php echo MAIN phpinfo();"),
-                    request_path: String::from("/main.php"),
-                    name: String::from("main.php"),
+                        FileEntry {
+                            path: String::from("/tmp/main.php"),
+                            content_sha256: store_blob(blobs_dir, "<?php echo MAIN phpinfo();".as_bytes()).unwrap_or(String::new()),
+                            owner: Owner {
+                                name: String::from("admin6"),
+                                .. Default::default()
+                            },
+                            .. Default::default()
+                        },
+                    ),
+                    http_content: String::from("<?php echo INDEX phpinfo();"),
+                    docroot: String::from("/tmp"),
+                    name: String::from("example.com"),
                     .. Default::default()
                 },
             ),
@@ -509,16 +500,31 @@ mod tests {
         changeset.uuid = Uuid::new_v4();
         changeset.timestamp += 1111;
 
-        let root = Path::new("/tmp");
-        if env::set_current_dir(&root).is_ok() {
-            remove_dir_all(Path::new(".changesets")).unwrap_or(());
-            store_changeset(String::from("admin6"), origin_changeset.clone());
-            store_changeset(String::from("admin6"), changeset);
-            let all = all_changesets(String::from("admin6"));
-            assert!(all.len() == 2);
-            let tsmp = mostrecent_changeset(String::from("admin6")).timestamp;
-            assert!(tsmp == origin_changeset.timestamp + 1111, "Most recent timestamp isn't most recent?");
-        }
+        let store = SpiceStore::new("/tmp/yak-changesets-test", "s0");
+        remove_dir_all(Path::new(&store.root)).unwrap_or(());
+        store.store_changeset("admin6", &origin_changeset).unwrap();
+        store.store_changeset("admin6", &changeset).unwrap();
+
+        /* truncated file left by crash */
+        let broken = Changeset { uuid: Uuid::new_v4(), .. origin_changeset.clone() };
+        store.store_changeset("admin6", &broken).unwrap();
+        let broken_file = store.changeset_files("admin6")
+            .into_iter()
+            .map(|(_, path)| path)
+            .find(|path| path.to_str().unwrap().contains(broken.uuid.to_string().as_str()))
+            .unwrap();
+        let mut bytes = vec!();
+        File::open(&broken_file).unwrap().read_to_end(&mut bytes).unwrap();
+        File::create(&broken_file).unwrap().write_all(&bytes[..bytes.len() / 2]).unwrap();
+
+        let all = store.changesets("admin6").unwrap();
+        assert!(all.len() == 2);
+        assert!(!Path::new(&broken_file).exists());
+        assert!(store.quarantined_changesets("admin6").len() == 1);
+        let tsmp = all.last().unwrap().timestamp;
+        assert!(tsmp == origin_changeset.timestamp + 1111, "Most recent timestamp isn't most recent?");
+        assert!(all[0].entries[0].files.len() == 2);
+
         /* NOTE: you can put .changesets/ from any serve to /tmp/specials/S1 to process more "real life" examples */
         let specials = Path::new("/tmp/specials/S1");
         if specials.exists() {
@@ -536,7 +542,7 @@ mod tests {
                     let user = entry.path().to_str().unwrap_or("").split("/").last().unwrap_or("");
                     if user.len() > 0 {
                         debug!("Processing user: {}", user);
-                        let all = all_changesets(String::from(user));
+                        let all = all_changesets(String::from(user)).unwrap();
                         assert!(all.len() > 0, format!("Changeset dir empty for user: {}?", user));
                         let most_recent = mostrecent_changeset_json(String::from(user));
                         assert!(most_recent.timestamp > 10000000, "Timestamp is too small?");
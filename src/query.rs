@@ -0,0 +1,264 @@
+use process::*;
+
+#[cfg(feature = "query")]
+use rusqlite::Connection;
+#[cfg(feature = "query")]
+use rusqlite::types::ToSql;
+
+
+/* current scan results of each user, one row per domain, file, probe and finding. Changesets stay the source of truth */
+#[cfg(feature = "query")]
+const SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS users (host TEXT NOT NULL, name TEXT NOT NULL, changeset TEXT NOT NULL, timestamp INTEGER NOT NULL, PRIMARY KEY (host, name));
+    CREATE TABLE IF NOT EXISTS domains (host TEXT NOT NULL, user TEXT NOT NULL, name TEXT NOT NULL, docroot TEXT, app_type TEXT, version TEXT,
+        version_major INTEGER, version_key TEXT, interpreter TEXT,
+        outdated INTEGER, zombie INTEGER, state TEXT, http_status_code INTEGER, http_response_time INTEGER, https_status_code INTEGER, https_response_time INTEGER,
+        PRIMARY KEY (host, user, name));
+    CREATE TABLE IF NOT EXISTS files (host TEXT NOT NULL, user TEXT NOT NULL, domain TEXT NOT NULL, path TEXT NOT NULL, raw_sha256 TEXT, size INTEGER, mode INTEGER,
+        modified INTEGER, lang TEXT, encoding TEXT, suspicion INTEGER, owner TEXT);
+    CREATE TABLE IF NOT EXISTS probes (host TEXT NOT NULL, user TEXT NOT NULL, domain TEXT NOT NULL, url TEXT NOT NULL, status_code INTEGER, content_size INTEGER, response_time INTEGER);
+    CREATE TABLE IF NOT EXISTS findings (host TEXT NOT NULL, user TEXT NOT NULL, domain TEXT NOT NULL, path TEXT NOT NULL, kind TEXT NOT NULL, name TEXT, severity TEXT);
+    CREATE INDEX IF NOT EXISTS files_of_domain ON files (host, user, domain);
+    CREATE INDEX IF NOT EXISTS findings_of_domain ON findings (host, user, domain);
+";
+
+
+#[cfg(not(feature = "query"))]
+const NOT_BUILT: &'static str = "Query store isn't available in this build. Rebuild with: cargo build --release --features query";
+
+
+#[cfg(feature = "query")]
+fn open(db_file: &str) -> Result<Connection, String> {
+    match Path::new(db_file).parent() {
+        Some(dir) => match create_dir_all(dir) {
+            Ok(_) => {},
+            Err(err) => return Err(format!("Failed to create dir: {:?}. Cause: {}", dir, err)),
+        },
+        None => {},
+    }
+    let connection = match Connection::open(db_file) {
+        Ok(connection) => connection,
+        Err(err) => return Err(format!("Failed to open query store: {}. Cause: {}", db_file, err)),
+    };
+    /* users are scanned in parallel, each one writes through its own connection */
+    match connection.execute_batch(&format!("PRAGMA busy_timeout = 30000; {}", SCHEMA)) {
+        Ok(_) => Ok(connection),
+        Err(err) => Err(format!("Failed to create schema of query store: {}. Cause: {}", db_file, err)),
+    }
+}
+
+
+/* connection for queries of users, which can't change query store */
+#[cfg(feature = "query")]
+fn open_read_only(db_file: &str) -> Result<Connection, String> {
+    let connection = try!(open(db_file));
+    match connection.execute_batch("PRAGMA query_only = 1;") {
+        Ok(_) => Ok(connection),
+        Err(err) => Err(format!("Failed to open query store: {} as read only. Cause: {}", db_file, err)),
+    }
+}
+
+
+/* replaces all rows of user with given materialized state, in single transaction */
+#[cfg(feature = "query")]
+fn store_state(connection: &Connection, host: &str, user_name: &str, state: &Changeset) -> Result<(), String> {
+    let (host, user_name) = (host.to_string(), user_name.to_string());
+    let execute = |sql: &str, params: &[&ToSql]| -> Result<(), String> {
+        match connection.execute(sql, params) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Failed to update query store of user: {}. Cause: {}", user_name, err)),
+        }
+    };
+    try!(execute("BEGIN IMMEDIATE", &[]));
+    let stored = (|| -> Result<(), String> {
+        try!(execute("DELETE FROM users WHERE host = ?1 AND name = ?2", &[&host, &user_name]));
+        for table in vec!("domains", "files", "probes", "findings") {
+            try!(execute(&format!("DELETE FROM {} WHERE host = ?1 AND user = ?2", table), &[&host, &user_name]));
+        }
+        try!(execute("INSERT INTO users VALUES (?1, ?2, ?3, ?4)",
+            &[&host, &user_name, &state.uuid.to_string(), &(state.timestamp as i64)]));
+        for domain in state.entries.iter() {
+            let app_type = domain.app_type.as_ref().map(|app_type| format!("{:?}", app_type)).unwrap_or(String::new());
+            let state_name = domain.states.first().map(|state| format!("{:?}", state)).unwrap_or(String::new());
+            let version_major: Option<i64> = domain.version.split('.').next().and_then(|major| major.parse().ok());
+            try!(execute("INSERT INTO domains VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                &[&host, &user_name, &domain.name, &domain.docroot, &app_type, &domain.version,
+                  &version_major, &sortable_version(&domain.version), &domain.interpreter,
+                  &(domain.outdated as i64), &(domain.zombie as i64), &state_name,
+                  &(domain.http_status_code as i64), &(domain.http_response_time as i64),
+                  &(domain.https_status_code as i64), &(domain.https_response_time as i64)]));
+            for file in domain.files.iter() {
+                try!(execute("INSERT INTO files VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    &[&host, &user_name, &domain.name, &file.path, &file.raw_sha256, &(file.size as i64), &(file.mode as i64),
+                      &file.modified, &file.lang, &file.encoding, &(file.suspicion as i64), &file.owner.name]));
+                for hit in file.signature_hits.iter() {
+                    try!(execute("INSERT INTO findings VALUES (?1, ?2, ?3, ?4, 'signature', ?5, ?6)",
+                        &[&host, &user_name, &domain.name, &file.path, &hit.name, &format!("{:?}", hit.severity)]));
+                }
+            }
+            for diff in domain.fs_diffs.iter() {
+                try!(execute("INSERT INTO findings VALUES (?1, ?2, ?3, ?4, 'fs_diff', ?5, '')",
+                    &[&host, &user_name, &domain.name, &diff.path, &format!("{:?}", diff.kind)]));
+            }
+            for probe in domain.probes.iter() {
+                try!(execute("INSERT INTO probes VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    &[&host, &user_name, &domain.name, &probe.url, &(probe.status_code as i64), &(probe.content_size as i64), &(probe.response_time as i64)]));
+            }
+        }
+        Ok(())
+    })();
+    match stored {
+        Ok(_) => execute("COMMIT", &[]),
+        Err(err) => {
+            execute("ROLLBACK", &[]).unwrap_or(());
+            Err(err)
+        },
+    }
+}
+
+
+/* version with each component zero padded, so text comparison matches version order: 4.5.2 => 000004.000005.000002 */
+#[cfg(feature = "query")]
+fn sortable_version(version: &str) -> String {
+    if version.is_empty() {
+        return String::new()
+    }
+    version
+        .split('.')
+        .map(|part| {
+            let digits: String = part.chars().take_while(|c| c.is_digit(10)).collect();
+            format!("{:0>6}", digits)
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+
+/* column names and rows of query result, each value as text (NULL as empty string) */
+#[cfg(feature = "query")]
+fn select(connection: &Connection, sql: &str) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    let mut statement = match connection.prepare(sql) {
+        Ok(statement) => statement,
+        Err(err) => return Err(format!("Invalid query: '{}'. Cause: {}", sql, err)),
+    };
+    let columns: Vec<String> = statement.column_names().into_iter().map(|name| name.to_string()).collect();
+    let mut rows = match statement.query(&[]) {
+        Ok(rows) => rows,
+        Err(err) => return Err(format!("Failed to run query: '{}'. Cause: {}", sql, err)),
+    };
+    let mut result = vec!();
+    while let Some(row) = rows.next() {
+        let row = match row {
+            Ok(row) => row,
+            Err(err) => return Err(format!("Failed to read result of query: '{}'. Cause: {}", sql, err)),
+        };
+        let mut values = vec!();
+        for index in 0..columns.len() as i32 {
+            let text: Result<Option<String>, _> = row.get_checked(index);
+            let integer: Result<Option<i64>, _> = row.get_checked(index);
+            let real: Result<Option<f64>, _> = row.get_checked(index);
+            let value = match (text, integer, real) {
+                (Ok(value), _, _) => value.unwrap_or(String::new()),
+                (_, Ok(value), _) => value.map(|value| value.to_string()).unwrap_or(String::new()),
+                (_, _, Ok(value)) => value.map(|value| value.to_string()).unwrap_or(String::new()),
+                _ => String::from("<blob>"),
+            };
+            values.push(value);
+        }
+        result.push(values);
+    }
+    Ok((columns, result))
+}
+
+
+#[cfg(feature = "query")]
+pub fn update_query_store(host: &str, user_name: &str, state: &Changeset) -> Result<(), String> {
+    let connection = try!(open(&config().query_db));
+    store_state(&connection, host, user_name, state)
+}
+
+
+/* query store is optional. Without it there's nothing to update */
+#[cfg(not(feature = "query"))]
+pub fn update_query_store(_: &str, _: &str, _: &Changeset) -> Result<(), String> {
+    Ok(())
+}
+
+
+#[cfg(feature = "query")]
+pub fn run_query(sql: &str) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    let connection = try!(open_read_only(&config().query_db));
+    select(&connection, sql)
+}
+
+
+#[cfg(not(feature = "query"))]
+pub fn run_query(_: &str) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    Err(String::from(NOT_BUILT))
+}
+
+
+/* fills query store again from current state of each user of this host */
+#[cfg(feature = "query")]
+pub fn rebuild_query_store(states: Vec<(String, Changeset)>) -> Result<usize, String> {
+    let db_file = config().query_db;
+    remove_file(&db_file).unwrap_or(());
+    let connection = try!(open(&db_file));
+    let host = config().host_name();
+    for &(ref user_name, ref state) in states.iter() {
+        try!(store_state(&connection, &host, user_name, state));
+    }
+    Ok(states.len())
+}
+
+
+#[cfg(not(feature = "query"))]
+pub fn rebuild_query_store(_: Vec<(String, Changeset)>) -> Result<usize, String> {
+    Err(String::from(NOT_BUILT))
+}
+
+
+#[cfg(all(test, feature = "query"))]
+#[test]
+fn query_store_test() {
+    let db_file = "/tmp/yak-query-test/query.db";
+    remove_file(db_file).unwrap_or(());
+    let connection = open(db_file).unwrap();
+
+    let domain = |name: &str, app_type: WebAppTypes, version: &str, status: u32| DomainEntry {
+        name: name.to_string(),
+        app_type: Some(app_type),
+        version: version.to_string(),
+        http_status_code: status,
+        files: vec!(FileEntry { path: format!("/{}/index.php", name), .. Default::default() }),
+        .. Default::default()
+    };
+    let state = Changeset {
+        entries: vec!(
+            domain("a.com", WebAppTypes::WordPress, "4.5.2", 500),
+            domain("b.com", WebAppTypes::WordPress, "5.1", 500),
+            domain("c.com", WebAppTypes::Joomla, "3.6", 500),
+            domain("d.com", WebAppTypes::WordPress, "10.0", 500),
+        ),
+        .. Default::default()
+    };
+    store_state(&connection, "s1", "admin6", &state).unwrap();
+    store_state(&connection, "s1", "admin6", &state).unwrap(); /* previous rows are replaced */
+
+    let (columns, rows) = select(&connection,
+        "SELECT user, name, http_status_code FROM domains WHERE app_type = 'WordPress' AND version_major < 5 AND http_status_code = 500").unwrap();
+    assert!(columns == vec!(String::from("user"), String::from("name"), String::from("http_status_code")));
+    assert!(rows == vec!(vec!(String::from("admin6"), String::from("a.com"), String::from("500"))));
+    let (_, rows) = select(&connection, "SELECT name FROM domains WHERE app_type = 'WordPress' ORDER BY version_key").unwrap();
+    assert!(rows == vec!(vec!(String::from("a.com")), vec!(String::from("b.com")), vec!(String::from("d.com"))), "10.0 should sort after 5.1");
+    assert!(sortable_version("4.5.2") < sortable_version("4.10") && sortable_version("") == "");
+    let (_, rows) = select(&connection, "SELECT COUNT(*) FROM files").unwrap();
+    assert!(rows[0][0] == "4");
+    assert!(select(&connection, "SELECT * FROM nothing").is_err());
+
+    let read_only = open_read_only(db_file).unwrap();
+    assert!(select(&read_only, "DELETE FROM domains").is_err());
+    assert!(select(&read_only, "DROP TABLE files").is_err());
+    let (_, rows) = select(&read_only, "SELECT COUNT(*) FROM domains").unwrap();
+    assert!(rows[0][0] == "4", "Query shouldn't change query store");
+}
@@ -0,0 +1,103 @@
+use process::*;
+use delta::{delta_of, materialize};
+
+use std::collections::HashSet;
+
+
+const DAY: u64 = 24 * 60 * 60 * 1000; /* changeset timestamps are in miliseconds */
+
+
+/* uuids of changesets kept by retention policy: last N, newest of each of last D days and newest of each of last W weeks */
+pub fn retained(changesets: &[Changeset], keep_last: usize, keep_daily: usize, keep_weekly: usize, now: u64) -> HashSet<Uuid> {
+    let mut newest_first: Vec<&Changeset> = changesets.iter().collect();
+    newest_first.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+
+    let mut keep = HashSet::new();
+    match newest_first.first() {
+        Some(newest) => { keep.insert(newest.uuid); }, /* current state is always kept */
+        None => return keep,
+    }
+    for changeset in newest_first.iter().take(keep_last) {
+        keep.insert(changeset.uuid);
+    }
+    for &(period, count) in [(DAY, keep_daily), (7 * DAY, keep_weekly)].iter() {
+        let mut periods = HashSet::new();
+        for changeset in newest_first.iter() {
+            let age = now.saturating_sub(changeset.timestamp) / period;
+            if (age as usize) < count && periods.insert(age) {
+                keep.insert(changeset.uuid);
+            }
+        }
+    }
+    keep
+}
+
+
+/* timestamp sorted changesets of user split into: changesets to store again and changesets to remove.
+   Kept delta which parent is removed is squashed with it (and all removed changesets between), so chain stays consistent */
+pub fn prune_chain(changesets: &[Changeset], keep: &HashSet<Uuid>) -> Result<(Vec<Changeset>, Vec<Changeset>), String> {
+    let mut rewritten = vec!();
+    let mut previous: Option<Changeset> = None; /* state at previous kept changeset */
+    for changeset in changesets.iter().filter(|e| keep.contains(&e.uuid)) {
+        let state = match materialize(changesets, changeset.uuid) {
+            Some(state) => state,
+            None => return Err(format!("Failed to materialize state at changeset: {}", changeset.uuid)),
+        };
+        let linked = changeset.full || previous.as_ref().map(|e| e.uuid == changeset.parent).unwrap_or(false);
+        if !linked {
+            rewritten.push(match previous {
+                Some(ref previous_state) => delta_of(previous_state, state.clone()),
+                None => Changeset { parent: root_uuid(), .. state.clone() }, /* oldest kept changeset becomes full snapshot */
+            });
+        }
+        previous = Some(state);
+    }
+    let removed = changesets.iter().filter(|e| !keep.contains(&e.uuid)).cloned().collect();
+    Ok((rewritten, removed))
+}
+
+
+#[cfg(test)]
+#[test]
+fn prune_chain_test() {
+    let file = |path: &str, sha: &str| FileEntry { path: path.to_string(), raw_sha256: sha.to_string(), .. Default::default() };
+    let scan = |timestamp: u64, files: Vec<FileEntry>| Changeset {
+        timestamp: timestamp,
+        entries: vec!(DomainEntry { name: String::from("example.com"), files: files, .. Default::default() }),
+        .. Default::default()
+    };
+    let now = 100 * DAY;
+    let scans = vec!(
+        scan(now - 20 * DAY, vec!(file("/index.php", "1"))),
+        scan(now - 2 * DAY - 10, vec!(file("/index.php", "1"), file("/a.php", "2"))),
+        scan(now - 2 * DAY, vec!(file("/index.php", "1"), file("/a.php", "3"))),
+        scan(now - DAY, vec!(file("/index.php", "4"), file("/a.php", "3"))),
+        scan(now, vec!(file("/index.php", "4"))),
+    );
+    let mut changesets = vec!(scans[0].clone());
+    for scanned in scans.iter().skip(1) {
+        let state = materialize(&changesets, changesets.last().unwrap().uuid).unwrap();
+        changesets.push(delta_of(&state, scanned.clone()));
+    }
+
+    let keep = retained(&changesets, 1, 3, 0, now);
+    let expected: HashSet<Uuid> = vec!(changesets[2].uuid, changesets[3].uuid, changesets[4].uuid).into_iter().collect();
+    assert!(keep == expected, format!("Kept: {:?}", keep));
+    assert!(retained(&changesets, 0, 0, 0, now).len() == 1, "Most recent changeset should be always kept");
+    assert!(retained(&changesets, 0, 0, 3, now).contains(&changesets[0].uuid));
+    assert!(retained(&[], 1, 1, 1, now).is_empty());
+
+    let (rewritten, removed) = prune_chain(&changesets, &keep).unwrap();
+    assert!(removed.len() == 2);
+    assert!(rewritten.len() == 1 && rewritten[0].uuid == changesets[2].uuid && rewritten[0].full);
+
+    let mut pruned: Vec<Changeset> = changesets.into_iter().filter(|e| keep.contains(&e.uuid) && e.uuid != rewritten[0].uuid).collect();
+    pruned.insert(0, rewritten[0].clone());
+    for (index, scanned) in scans.iter().skip(2).enumerate() {
+        let state = materialize(&pruned, pruned[index].uuid).unwrap();
+        let paths: Vec<(String, String)> = state.entries[0].files.iter().map(|e| (e.path.clone(), e.raw_sha256.clone())).collect();
+        let mut expected: Vec<(String, String)> = scanned.entries[0].files.iter().map(|e| (e.path.clone(), e.raw_sha256.clone())).collect();
+        expected.sort();
+        assert!(paths == expected, format!("State at: {} differs: {:?}", index, paths));
+    }
+}
@@ -0,0 +1,267 @@
+use process::*;
+use yara::{YaraRule, parse_yara};
+
+use rustc_serialize::hex::FromHex;
+use regex::bytes;
+
+
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq, PartialOrd)]
+pub enum RuleSeverity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+
+/* signature matched in content of scanned file */
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub struct SignatureHit {
+    pub name: String,
+    pub severity: RuleSeverity,
+    pub offset: usize, /* offset of first match in file content */
+}
+
+
+/* rule as defined in JSON rule file, only one of: regex, literal, hex should be given */
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct RuleDefinition {
+    pub name: String,
+    pub severity: RuleSeverity,
+    pub description: String,
+    pub regex: Option<String>,
+    pub literal: Option<String>,
+    pub hex: Option<String>, /* literal byte pattern, f.e.: "3c3f706870" */
+}
+
+
+pub enum Pattern {
+    Regex(bytes::Regex), /* matched on raw bytes, so offsets don't drift after invalid UTF-8 */
+    Literal(Vec<u8>),
+    Yara(YaraRule),
+}
+
+
+pub struct Signature {
+    pub name: String,
+    pub severity: RuleSeverity,
+    pub description: String,
+    pub pattern: Pattern,
+}
+
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+
+impl Signature {
+    pub fn from_definition(definition: RuleDefinition) -> Result<Signature, String> {
+        let pattern = match (definition.regex, definition.literal, definition.hex) {
+            (Some(regex), None, None) => match bytes::Regex::new(&regex) {
+                Ok(compiled) => Pattern::Regex(compiled),
+                Err(err) => return Err(format!("Invalid regex of rule: {}. Cause: {}", definition.name, err)),
+            },
+            (None, Some(literal), None) => Pattern::Literal(literal.into_bytes()),
+            (None, None, Some(hex)) => match hex.replace(" ", "").from_hex() {
+                Ok(bytes) => Pattern::Literal(bytes),
+                Err(err) => return Err(format!("Invalid hex pattern of rule: {}. Cause: {}", definition.name, err)),
+            },
+            _ => return Err(format!("Rule: {} should define exactly one of: regex, literal, hex", definition.name)),
+        };
+        Ok(Signature {
+            name: definition.name,
+            severity: definition.severity,
+            description: definition.description,
+            pattern: pattern,
+        })
+    }
+
+    /* severity and description are read from rule meta, High severity by default */
+    pub fn from_yara(rule: YaraRule) -> Signature {
+        let severity = match rule.meta.get("severity").map(|severity| severity.to_lowercase()) {
+            Some(ref severity) if severity == "low" => RuleSeverity::Low,
+            Some(ref severity) if severity == "medium" => RuleSeverity::Medium,
+            Some(ref severity) if severity == "critical" => RuleSeverity::Critical,
+            _ => RuleSeverity::High,
+        };
+        Signature {
+            name: rule.name.clone(),
+            severity: severity,
+            description: rule.meta.get("description").cloned().unwrap_or(String::new()),
+            pattern: Pattern::Yara(rule),
+        }
+    }
+
+    /* byte offset of first match in given content */
+    pub fn find(&self, content: &[u8], filesize: u64) -> Option<usize> {
+        match self.pattern {
+            Pattern::Regex(ref regex) => regex.find(content).map(|(start, _)| start),
+            Pattern::Literal(ref literal) => find_bytes(content, literal),
+            Pattern::Yara(ref rule) => rule.find(content, filesize),
+        }
+    }
+}
+
+
+pub fn parse_signatures(content: &str) -> Result<Vec<Signature>, String> {
+    let definitions: Vec<RuleDefinition> = match json::decode(content) {
+        Ok(definitions) => definitions,
+        Err(err) => return Err(format!("Rule file parse failure: {}", err)),
+    };
+    let mut signatures = vec!();
+    for definition in definitions {
+        signatures.push(try!(Signature::from_definition(definition)));
+    }
+    Ok(signatures)
+}
+
+
+pub fn parse_yara_signatures(content: &str) -> Result<Vec<Signature>, String> {
+    parse_yara(content).map(|rules| rules.into_iter().map(Signature::from_yara).collect())
+}
+
+
+/* loads all JSON and YARA (.yar, .yara) rule files from given dir */
+pub fn load_signatures(signatures_dir: &str) -> Vec<Signature> {
+    let mut signatures = vec!();
+    let walker = WalkDir::new(signatures_dir)
+        .follow_links(false)
+        .max_depth(1)
+        .into_iter();
+
+    for entry in walker
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file()) {
+
+        let parse: fn(&str) -> Result<Vec<Signature>, String> = match entry.path().extension().and_then(|e| e.to_str()) {
+            Some("json") => parse_signatures,
+            Some("yar") | Some("yara") => parse_yara_signatures,
+            _ => continue,
+        };
+        let mut content = String::new();
+        match File::open(entry.path()).and_then(|file| BufReader::new(file).read_to_string(&mut content)) {
+            Ok(_) => {
+                match parse(&content) {
+                    Ok(mut loaded) => {
+                        debug!("Loaded {} signatures from: {:?}", loaded.len(), entry.path());
+                        signatures.append(&mut loaded);
+                    },
+                    Err(err) => error!("Rule file: {:?} skipped. {}", entry.path(), err),
+                }
+            },
+            Err(err) => error!("Failed to read rule file: {:?}. Cause: {}", entry.path(), err),
+        }
+    }
+    info!("Loaded {} signatures from: {}", signatures.len(), signatures_dir);
+    signatures
+}
+
+
+lazy_static! {
+    /* loaded on first use, after configuration is read */
+    static ref SIGNATURES: Vec<Signature> = load_signatures(&config().signatures_dir);
+}
+
+
+/* filesize is size of whole scanned file, content might be only its beginning */
+pub fn scan_with(signatures: &Vec<Signature>, content: &[u8], filesize: u64) -> Vec<SignatureHit> {
+    signatures
+        .iter()
+        .filter_map(|signature| {
+            signature.find(content, filesize).map(|offset| SignatureHit {
+                name: signature.name.clone(),
+                severity: signature.severity.clone(),
+                offset: offset,
+            })
+        })
+        .collect()
+}
+
+
+/* matches content of scanned file against all loaded signatures */
+pub fn scan_signatures(content: &[u8], filesize: u64) -> Vec<SignatureHit> {
+    scan_with(&SIGNATURES, content, filesize)
+}
+
+
+/* matches head and tail fragments of file bigger than read limit. Offsets of tail hits are offsets in whole file */
+pub fn scan_head_and_tail(head: &[u8], tail: &[u8], tail_offset: usize, filesize: u64) -> Vec<SignatureHit> {
+    scan_fragments_with(&SIGNATURES, head, tail, tail_offset, filesize)
+}
+
+
+fn scan_fragments_with(signatures: &Vec<Signature>, head: &[u8], tail: &[u8], tail_offset: usize, filesize: u64) -> Vec<SignatureHit> {
+    if tail_offset == head.len() { /* nothing skipped between fragments */
+        let mut content = head.to_vec();
+        content.extend_from_slice(tail);
+        return scan_with(signatures, &content, filesize)
+    }
+    let mut hits = scan_with(signatures, head, filesize);
+    for hit in scan_with(signatures, tail, filesize) {
+        if !hits.iter().any(|found| found.name == hit.name) {
+            hits.push(SignatureHit { offset: hit.offset + tail_offset, .. hit });
+        }
+    }
+    hits
+}
+
+
+#[cfg(test)]
+#[test]
+fn scan_signatures_test() {
+    let signatures = parse_signatures(r#"[
+        {"name": "php_eval_base64", "severity": "High", "description": "eval of base64 encoded code", "regex": "eval\\s*\\(\\s*base64_decode\\s*\\("},
+        {"name": "php_gzinflate_rot13", "severity": "Critical", "description": "rot13 obfuscated payload", "literal": "gzinflate(str_rot13("},
+        {"name": "elf_binary", "severity": "Medium", "description": "ELF binary", "hex": "7f 45 4c 46"}
+    ]"#).unwrap();
+    assert!(signatures.len() == 3);
+
+    let hits = scan_with(&signatures, b"<?php $a = 1; eval ( base64_decode('ZWNobyAxOw==')); ?>", 56);
+    assert!(hits == vec!(SignatureHit { name: String::from("php_eval_base64"), severity: RuleSeverity::High, offset: 14 }), format!("Hits: {:?}", hits));
+
+    let hits = scan_with(&signatures, b"\x7fELF\x02\x01 gzinflate(str_rot13(", 26);
+    assert!(hits.len() == 2);
+    assert!(hits[0].name == "php_gzinflate_rot13" && hits[0].offset == 7);
+    assert!(hits[1].name == "elf_binary" && hits[1].offset == 0);
+
+    assert!(scan_with(&signatures, b"<?php echo 'Hello';", 19).is_empty());
+    let hits = scan_with(&signatures, b"\xff\xfe\xff eval(base64_decode(", 23);
+    assert!(hits.len() == 1 && hits[0].offset == 4, "Offset should be counted in bytes of content");
+    let hits = scan_fragments_with(&signatures, b"<?php echo 1;", b"gzinflate(str_rot13(", 1000, 1020);
+    assert!(hits.len() == 1 && hits[0].offset == 1000, "Offset of tail hit should be offset in whole file");
+    assert!(scan_fragments_with(&signatures, b"<?php eval(base6", b"4_decode(", 16, 25).len() == 1, "Adjacent fragments should be scanned as one");
+
+    let yara = parse_yara_signatures(r#"
+        rule small_dropper {
+            meta:
+                description = "ELF dropper hidden in PHP file"
+                severity = "critical"
+            strings:
+                $php = "<?php"
+                $elf = { 7F 45 4C 46 }
+            condition:
+                all of them and filesize < 1KB
+        }
+        rule unrated { condition: filesize > 1MB }
+    "#).unwrap();
+    assert!(yara[0].severity == RuleSeverity::Critical && yara[0].description == "ELF dropper hidden in PHP file");
+    assert!(yara[1].severity == RuleSeverity::High);
+    let content = b"<?php $bin = '\x7fELF'; ?>";
+    assert!(scan_with(&yara, content, 512) == vec!(SignatureHit { name: String::from("small_dropper"), severity: RuleSeverity::Critical, offset: 0 }));
+    assert!(scan_with(&yara, content, 4096).is_empty());
+    assert!(scan_with(&yara, content, 2 * 1024 * 1024).len() == 1);
+
+    for invalid in vec!(
+        r#"[{"name": "no_pattern", "severity": "Low", "description": ""}]"#,
+        r#"[{"name": "two_patterns", "severity": "Low", "description": "", "regex": "a", "literal": "a"}]"#,
+        r#"[{"name": "bad_regex", "severity": "Low", "description": "", "regex": "("}]"#,
+        r#"[{"name": "bad_hex", "severity": "Low", "description": "", "hex": "zz"}]"#,
+        r#"[{"name": "bad_severity", "severity": "Huge", "description": "", "literal": "a"}]"#,
+    ) {
+        assert!(parse_signatures(invalid).is_err(), invalid);
+    }
+}
@@ -0,0 +1,172 @@
+use process::*;
+
+use std::fmt;
+use std::fmt::Display;
+use std::collections::BTreeMap;
+
+
+/* checksums of clean, fresh installation of given web app version */
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub struct SpiceBaseline {
+    pub app_type: WebAppTypes,
+    pub version: String,
+    pub interpreter: String, /* minimal version required by web app */
+    pub supported: bool, /* if false, then app is a zombie with no support from devs */
+    pub checksums: BTreeMap<String, String>, /* relative file path => checksum */
+}
+
+
+impl SpiceBaseline {
+    pub fn checksum_of(&self, relative_path: &str) -> Option<&String> {
+        self.checksums.get(relative_path)
+    }
+}
+
+
+impl Display for SpiceBaseline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match json::encode(&self) {
+            Ok(result) => write!(f, "{}", result),
+            Err(err) => write!(f, "Failure serializing JSON for SpiceBaseline! Cause: {}", err)
+        }
+    }
+}
+
+
+pub fn baseline_file(spice_dir: &str, app_type: &WebAppTypes, version: &str) -> String {
+    format!("{}/{:?}/{}.json", spice_dir, app_type, version)
+}
+
+
+/* learns checksums of all files of pristine web app release extracted to given dir */
+pub fn gather_baseline(release_dir: &str, app_type: WebAppTypes, version: &str, interpreter: &str, supported: bool) -> Result<SpiceBaseline, String> {
+    if !Path::new(release_dir).is_dir() {
+        return Err(format!("Not a directory: {}", release_dir))
+    }
+    let mut checksums = BTreeMap::new();
+    let walker = WalkDir::new(release_dir)
+        .follow_links(false)
+        .max_open(256)
+        .into_iter();
+
+    for entry in walker
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file()) {
+
+        let abs_path = match entry.path().to_str() {
+            Some(path) => path.to_string(),
+            None => {
+                warn!("Skipping non UTF-8 path: {:?}", entry.path());
+                continue
+            },
+        };
+        if !valid_file_extensions(&abs_path) { /* same files as processed by scanner */
+            debug!("Skipped: {}", abs_path);
+            continue
+        }
+        let relative_path = abs_path[release_dir.len()..].trim_left_matches('/').to_string();
+        match File::open(&abs_path) {
+            Ok(file) => {
+                /* same hashing as used by scanner, so checksums are comparable */
+                match raw_checksums_of(BufReader::new(file)) {
+                    Ok((raw_sha1, _)) => {
+                        checksums.insert(relative_path, raw_sha1);
+                    },
+                    Err(err) => return Err(format!("Failed to read file: {}. Cause: {}", abs_path, err)),
+                }
+            },
+            Err(err) => return Err(format!("Failed to open file: {}. Cause: {}", abs_path, err)),
+        }
+    }
+    if checksums.is_empty() {
+        return Err(format!("No files to checksum found in: {}", release_dir))
+    }
+    Ok(SpiceBaseline {
+        app_type: app_type,
+        version: version.to_string(),
+        interpreter: interpreter.to_string(),
+        supported: supported,
+        checksums: checksums,
+    })
+}
+
+
+pub fn store_baseline(spice_dir: &str, baseline: &SpiceBaseline) -> Result<String, String> {
+    let file_name = baseline_file(spice_dir, &baseline.app_type, &baseline.version);
+    match Path::new(&file_name).parent() {
+        Some(dir) => match create_dir_all(dir) {
+            Ok(_) => {},
+            Err(err) => return Err(format!("Failed to create dir: {:?}. Cause: {}", dir, err)),
+        },
+        None => {},
+    }
+    match File::create(&file_name) {
+        Ok(file) => {
+            let mut writer = BufWriter::new(file);
+            match writer.write_all(baseline.to_string().as_bytes()).and_then(|_| writer.flush()) {
+                Ok(_) => Ok(file_name),
+                Err(err) => Err(format!("Failed to write baseline: {}. Cause: {}", file_name, err)),
+            }
+        },
+        Err(err) => Err(format!("Failed to create baseline: {}. Cause: {}", file_name, err)),
+    }
+}
+
+
+/* pristine checksums of given web app version, if it was gathered before */
+pub fn load_baseline(spice_dir: &str, app_type: &WebAppTypes, version: &str) -> Option<SpiceBaseline> {
+    let file_name = baseline_file(spice_dir, app_type, version);
+    let mut content = String::new();
+    match File::open(&file_name) {
+        Ok(file) => {
+            match BufReader::new(file).read_to_string(&mut content) {
+                Ok(_) => {},
+                Err(err) => {
+                    error!("Failed to read baseline: {}. Cause: {}", file_name, err);
+                    return None
+                },
+            }
+        },
+        Err(_) => {
+            debug!("No baseline: {} for: {:?} version: {}", file_name, app_type, version);
+            return None
+        },
+    }
+    match json::decode(&content) {
+        Ok(baseline) => Some(baseline),
+        Err(err) => {
+            error!("Failed to decode baseline: {}. Cause: {}", file_name, err);
+            None
+        },
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn gather_store_load_baseline_test() {
+    let release_dir = "/tmp/yak-spice/wordpress";
+    let spice_dir = "/tmp/yak-spice/store";
+    remove_dir_all(Path::new("/tmp/yak-spice")).unwrap_or(());
+    for (file, content) in vec!(
+        ("index.php", "<?php define('WP_USE_THEMES', true); require('./wp-blog-header.php');"),
+        ("wp-includes/version.php", "<?php $wp_version = '4.5.2';"),
+        ("wp-includes/images/w-logo.png", "\u{89}PNG"),
+    ) {
+        let path = Path::new(release_dir).join(file);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+    }
+
+    let baseline = gather_baseline(release_dir, WebAppTypes::WordPress, "4.5.2", "5.2", true).unwrap();
+    assert!(baseline.checksums.len() == 2, format!("Checksums: {:?}", baseline.checksums));
+    assert!(baseline.checksum_of("index.php").is_some());
+    assert!(baseline.checksum_of("wp-includes/version.php").is_some());
+    assert!(baseline.checksum_of("wp-includes/images/w-logo.png").is_none());
+
+    let file_name = store_baseline(spice_dir, &baseline).unwrap();
+    assert!(file_name == "/tmp/yak-spice/store/WordPress/4.5.2.json");
+    assert!(load_baseline(spice_dir, &WebAppTypes::WordPress, "4.5.2") == Some(baseline));
+    assert!(load_baseline(spice_dir, &WebAppTypes::WordPress, "4.5.3") == None);
+    assert!(gather_baseline("/tmp/yak-spice/nonexistent", WebAppTypes::WordPress, "4.5.2", "5.2", true).is_err());
+}
@@ -0,0 +1,190 @@
+use process::*;
+use integrity::FsDiffKind;
+use signatures::RuleSeverity;
+
+
+/* higher value wins, when single verdict is needed */
+fn priority_of(state: &DomainStates) -> u8 {
+    match *state {
+        DomainStates::Hacked => 8,
+        DomainStates::Malicious => 7,
+        DomainStates::Suspected => 6,
+        DomainStates::Unresolvable => 5,
+        DomainStates::Broken => 4,
+        DomainStates::Empty => 3,
+        DomainStates::Warning => 2,
+        DomainStates::Unknown => 1,
+        DomainStates::Ok => 0,
+    }
+}
+
+
+fn state_of_severity(severity: &RuleSeverity) -> DomainStates {
+    match *severity {
+        RuleSeverity::Low => DomainStates::Warning,
+        RuleSeverity::Medium => DomainStates::Suspected,
+        RuleSeverity::High => DomainStates::Malicious,
+        RuleSeverity::Critical => DomainStates::Hacked,
+    }
+}
+
+
+/* states found in content of single file */
+pub fn file_states(file: &FileEntry, threshold: u32) -> Vec<DomainStates> {
+    let mut states: Vec<DomainStates> = file.signature_hits.iter().map(|hit| state_of_severity(&hit.severity)).collect();
+    if file.suspicion >= threshold {
+        states.push(DomainStates::Suspected);
+    }
+    states
+}
+
+
+/* states derived from web app release and integrity of its framework files */
+pub fn webapp_states(entry: &DomainEntry) -> Vec<DomainStates> {
+    let mut states = vec!();
+    if entry.outdated || entry.zombie {
+        states.push(DomainStates::Warning);
+    }
+    for diff in entry.fs_diffs.iter() {
+        states.push(match diff.kind {
+            FsDiffKind::Modified | FsDiffKind::Extra => DomainStates::Suspected,
+            FsDiffKind::Missing => DomainStates::Warning,
+        });
+    }
+    states
+}
+
+
+/* states derived from HTTP and HTTPS probe of domain index. Status 410 is used for unresolvable domain */
+pub fn http_states(entry: &DomainEntry) -> Vec<DomainStates> {
+    let probes = vec!(
+        (entry.http_status_code, entry.http_content_size),
+        (entry.https_status_code, entry.https_content_size),
+    );
+    if probes.iter().all(|&(status, _)| status == 0) {
+        return vec!(DomainStates::Unknown)
+    }
+    if probes.iter().all(|&(status, _)| status == 410) {
+        return vec!(DomainStates::Unresolvable)
+    }
+    let reachable: Vec<&(u32, usize)> = probes.iter().filter(|&&(status, _)| status >= 200 && status < 400).collect();
+    if reachable.is_empty() {
+        return vec!(DomainStates::Broken)
+    }
+    if reachable.iter().all(|&&(status, size)| status < 300 && size == 0) {
+        return vec!(DomainStates::Empty)
+    }
+    vec!()
+}
+
+
+/* case insensitive lookup of severity level by name, f.e.: "pedantic" */
+pub fn severity_level_of(name: &str) -> Option<SeverityLevels> {
+    vec!(SeverityLevels::Normal, SeverityLevels::Pedantic, SeverityLevels::Psycho)
+        .into_iter()
+        .find(|level| format!("{:?}", level).to_lowercase() == name.to_lowercase())
+}
+
+
+/* escalates state according to given severity level */
+pub fn escalate(state: DomainStates, severity: &SeverityLevels) -> DomainStates {
+    match *severity {
+        SeverityLevels::Normal => state,
+        SeverityLevels::Pedantic => match state {
+            DomainStates::Warning => DomainStates::Suspected,
+            DomainStates::Empty | DomainStates::Unresolvable | DomainStates::Unknown => DomainStates::Broken,
+            other => other,
+        },
+        SeverityLevels::Psycho => match escalate(state, &SeverityLevels::Pedantic) {
+            DomainStates::Suspected | DomainStates::Malicious => DomainStates::Hacked,
+            other => other,
+        },
+    }
+}
+
+
+/* set of states of domain, from its probes and all files scanned in its document root */
+pub fn classify_domain(domain: &DomainEntry, threshold: u32, severity: &SeverityLevels) -> Vec<DomainStates> {
+    let mut states = vec!();
+    if domain.files.is_empty() {
+        states.push(DomainStates::Unknown);
+    }
+    states.extend(http_states(domain));
+    states.extend(webapp_states(domain));
+    for file in domain.files.iter() {
+        states.extend(file_states(file, threshold));
+    }
+    let mut unique: Vec<DomainStates> = vec!();
+    for state in states.into_iter().map(|state| escalate(state, severity)) {
+        if !unique.contains(&state) {
+            unique.push(state);
+        }
+    }
+    if unique.is_empty() {
+        unique.push(DomainStates::Ok);
+    }
+    unique.sort_by(|a, b| priority_of(b).cmp(&priority_of(a)));
+    unique
+}
+
+
+/* single, most important state of domain */
+pub fn verdict_of(states: &[DomainStates]) -> DomainStates {
+    states.iter().max_by_key(|state| priority_of(state)).cloned().unwrap_or(DomainStates::Unknown)
+}
+
+
+#[cfg(test)]
+#[test]
+fn classify_domain_test() {
+    use integrity::FsDiff;
+    use signatures::SignatureHit;
+
+    let healthy = DomainEntry {
+        name: String::from("example.com"),
+        files: vec!(FileEntry { path: String::from("/home/admin6/domains/example.com/public_html/index.php"), .. Default::default() }),
+        http_status_code: 200,
+        http_content_size: 1024,
+        https_status_code: 200,
+        https_content_size: 1024,
+        .. Default::default()
+    };
+    let normal = SeverityLevels::Normal;
+    assert!(classify_domain(&healthy, 50, &normal) == vec!(DomainStates::Ok));
+    assert!(classify_domain(&DomainEntry { files: vec!(), .. healthy.clone() }, 50, &normal) == vec!(DomainStates::Unknown));
+    assert!(classify_domain(&DomainEntry { http_status_code: 0, https_status_code: 0, .. healthy.clone() }, 50, &normal) == vec!(DomainStates::Unknown));
+
+    let unresolvable = DomainEntry { http_status_code: 410, https_status_code: 410, .. healthy.clone() };
+    assert!(classify_domain(&unresolvable, 50, &normal) == vec!(DomainStates::Unresolvable));
+
+    let broken = DomainEntry { http_status_code: 500, https_status_code: 404, .. healthy.clone() };
+    assert!(classify_domain(&broken, 50, &normal) == vec!(DomainStates::Broken));
+
+    let empty = DomainEntry { http_content_size: 0, https_status_code: 404, .. healthy.clone() };
+    assert!(classify_domain(&empty, 50, &normal) == vec!(DomainStates::Empty));
+
+    let redirected = DomainEntry { http_status_code: 301, http_content_size: 0, https_content_size: 0, .. healthy.clone() };
+    assert!(classify_domain(&redirected, 50, &normal) == vec!(DomainStates::Ok));
+
+    let mut infected = DomainEntry { outdated: true, .. healthy.clone() };
+    infected.files.push(FileEntry {
+        path: String::from("/home/admin6/domains/example.com/public_html/wp-content/uploads/cache.php"),
+        signature_hits: vec!(SignatureHit { name: String::from("php_eval_request"), severity: RuleSeverity::High, offset: 0 }),
+        suspicion: 75,
+        .. Default::default()
+    });
+    infected.fs_diffs = vec!(FsDiff { path: String::from("wp-login.php"), kind: FsDiffKind::Missing });
+
+    let states = classify_domain(&infected, 50, &normal);
+    assert!(states == vec!(DomainStates::Malicious, DomainStates::Suspected, DomainStates::Warning), format!("States: {:?}", states));
+    assert!(verdict_of(&states) == DomainStates::Malicious);
+    assert!(verdict_of(&[]) == DomainStates::Unknown);
+
+    let states = classify_domain(&infected, 50, &SeverityLevels::Pedantic);
+    assert!(states == vec!(DomainStates::Malicious, DomainStates::Suspected), format!("States: {:?}", states));
+    let states = classify_domain(&infected, 50, &SeverityLevels::Psycho);
+    assert!(states == vec!(DomainStates::Hacked), format!("States: {:?}", states));
+    assert!(classify_domain(&DomainEntry { files: vec!(), .. healthy }, 50, &SeverityLevels::Pedantic) == vec!(DomainStates::Broken));
+    assert!(severity_level_of("PSYCHO") == Some(SeverityLevels::Psycho));
+    assert!(severity_level_of("paranoid") == None);
+}
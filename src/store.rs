@@ -0,0 +1,760 @@
+use process::*;
+
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+use std::path::PathBuf;
+
+
+/*
+    SpiceStore layout:
+        ROOT/HOST_NAME/USER_NAME/domains/DOMAIN_TLD/.chsets/TIMESTAMP-UUID-WRITE.chset - part of changeset with changes of domain
+        ROOT/HOST_NAME/USER_NAME/.chsets/TIMESTAMP-UUID-WRITE.chset - changeset without changes of any domain
+        ROOT/HOST_NAME/USER_NAME/.chsets/TIMESTAMP-UUID.manifest - parts of changeset, written last
+        ROOT/HOST_NAME/USER_NAME/domains/DOMAIN_TLD.json - current state of domain, updated by each scan
+        ROOT/HOST_NAME/USER_NAME/quarantine/TIMESTAMP-UUID/ - manifest and parts of corrupted changeset
+        ROOT/HOST_NAME/USER_NAME/index.json - summaries of all changesets of user, see index.rs
+
+    Changeset of user is assembled back from parts listed in its manifest. Parts without manifest
+    are leftovers of interrupted writes and are never read. WRITE is unique id of single write.
+ */
+pub struct SpiceStore {
+    pub root: String,
+    pub host: String,
+}
+
+
+/* materialized state of domain, as of given changeset */
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct DomainState {
+    pub changeset: Uuid,
+    pub timestamp: u64,
+    pub domain: DomainEntry,
+}
+
+
+impl Display for DomainState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match json::encode(&self) {
+            Ok(result) => write!(f, "{}", result),
+            Err(err) => write!(f, "Failure serializing JSON for DomainState! Cause: {}", err)
+        }
+    }
+}
+
+
+/* failure of reading stored changeset file */
+#[derive(Debug)]
+pub enum ReadError {
+    Io(String), /* file can't be read right now, f.e. no permission or disk failure */
+    Corrupted(String), /* bad magic, unsupported version, checksum mismatch or undecodable content */
+}
+
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref cause) => write!(f, "{}", cause),
+            ReadError::Corrupted(ref cause) => write!(f, "{}", cause),
+        }
+    }
+}
+
+
+/* parts of changeset, stored after all of them are written. Changeset without manifest doesn't exist */
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub uuid: Uuid,
+    pub timestamp: u64,
+    pub parts: Vec<String>, /* relative to user dir */
+}
+
+
+const CHANGESETS_DIR: &'static str = ".chsets";
+
+
+impl SpiceStore {
+    pub fn new(root: &str, host: &str) -> SpiceStore {
+        SpiceStore {
+            root: root.to_string(),
+            host: host.to_string(),
+        }
+    }
+
+    /* store of this host */
+    pub fn local() -> SpiceStore {
+        let config = config();
+        SpiceStore::new(&config.store_dir, &config.host_name())
+    }
+
+    /* store of given host, under the same root */
+    pub fn of_host(host: &str) -> SpiceStore {
+        SpiceStore::new(&config().store_dir, host)
+    }
+
+    /* stores of all hosts under the same root. Data dirs kept under root, like default blobs dir, aren't hosts */
+    pub fn all_hosts() -> Vec<SpiceStore> {
+        let config = config();
+        let data_dirs = vec!(&config.blobs_dir, &config.spice_dir, &config.signatures_dir);
+        let mut hosts = vec!();
+        let walker = WalkDir::new(&config.store_dir)
+            .follow_links(false)
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter();
+
+        for entry in walker
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir())
+            .filter(|e| !data_dirs.iter().any(|dir| Path::new(dir) == e.path())) {
+            match entry.file_name().to_str() {
+                Some(name) => hosts.push(SpiceStore::of_host(name)),
+                None => warn!("Skipping non UTF-8 host dir: {:?}", entry.path()),
+            }
+        }
+        hosts.sort_by(|a, b| a.host.cmp(&b.host));
+        hosts
+    }
+
+    pub fn user_dir(&self, user_name: &str) -> String {
+        format!("{}/{}/{}", self.root, self.host, user_name)
+    }
+
+    pub fn domain_dir(&self, user_name: &str, domain: &str) -> String {
+        format!("{}/domains/{}", self.user_dir(user_name), domain)
+    }
+
+    pub fn quarantine_dir(&self, user_name: &str) -> String {
+        format!("{}/quarantine", self.user_dir(user_name))
+    }
+
+    fn changesets_dir(&self, user_name: &str, domain: Option<&str>) -> String {
+        match domain {
+            Some(domain) => format!("{}/{}", self.domain_dir(user_name, domain), CHANGESETS_DIR),
+            None => format!("{}/{}", self.user_dir(user_name), CHANGESETS_DIR),
+        }
+    }
+
+    /* names of all users that have anything stored */
+    pub fn users(&self) -> Vec<String> {
+        let mut users = vec!();
+        let walker = WalkDir::new(format!("{}/{}", self.root, self.host))
+            .follow_links(false)
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter();
+
+        for entry in walker
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir()) {
+            match entry.file_name().to_str() {
+                Some(name) => users.push(name.to_string()),
+                None => warn!("Skipping non UTF-8 user dir: {:?}", entry.path()),
+            }
+        }
+        users.sort();
+        users
+    }
+
+    /*
+        parts are written first, then manifest listing them is renamed into place,
+        so changeset is either complete or absent after crash
+     */
+    pub fn store_changeset(&self, user_name: &str, changeset: &Changeset) -> Result<(String, usize), String> {
+        let file_name = part_file_name(changeset);
+        let user_dir = self.user_dir(user_name);
+        let mut written: Vec<(String, String)> = vec!();
+        let mut bytes_written = 0;
+        for (domain, part) in parts_of(changeset) {
+            let dir = self.changesets_dir(user_name, domain.as_ref().map(|name| name.as_str()));
+            let target = format!("{}/{}", dir, file_name);
+            let encoded_bytes = try!(encode_changeset(&part));
+            match create_dir_all(&dir).and_then(|_| write_atomically(&target, &encoded_bytes)) {
+                Ok(_) => {
+                    bytes_written += encoded_bytes.len();
+                    written.push((dir, target));
+                },
+                Err(err) => {
+                    remove_written(&written);
+                    return Err(format!("Failed to store changeset: {}. Cause: {}", target, err))
+                },
+            }
+        }
+        let parts: Vec<String> = written.iter().map(|&(_, ref target)| target[user_dir.len() + 1..].to_string()).collect();
+        let manifest = Manifest {
+            uuid: changeset.uuid,
+            timestamp: changeset.timestamp,
+            parts: parts.clone(),
+        };
+        let manifest_dir = self.changesets_dir(user_name, None);
+        let manifest_file = self.manifest_file(user_name, changeset.timestamp, changeset.uuid);
+        let encoded_manifest = match json::encode(&manifest) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                remove_written(&written);
+                return Err(format!("Failed to encode manifest: {}. Cause: {}", manifest_file, err))
+            },
+        };
+        /* parts have to be persisted before manifest that commits them */
+        let committed = written.iter().fold(Ok(()), |result, &(ref dir, _)| result.and_then(|_| sync_dir(dir)))
+            .and_then(|_| create_dir_all(&manifest_dir))
+            .and_then(|_| write_atomically(&manifest_file, encoded_manifest.as_bytes()))
+            .and_then(|_| sync_dir(&manifest_dir));
+        match committed {
+            Ok(_) => {},
+            Err(err) => {
+                remove_written(&written);
+                return Err(format!("Failed to store changeset: {}. Cause: {}", manifest_file, err))
+            },
+        }
+        /* parts of the same changeset stored before, f.e. by prune, are superseded by this write */
+        match self.remove_parts(user_name, changeset.timestamp, changeset.uuid, &parts) {
+            Ok(_) => {},
+            Err(err) => warn!("{}", err),
+        }
+        self.index_changeset(user_name, changeset, parts, bytes_written);
+        Ok((user_dir, bytes_written))
+    }
+
+    pub fn manifest_file(&self, user_name: &str, timestamp: u64, uuid: Uuid) -> String {
+        format!("{}/{}", self.changesets_dir(user_name, None), manifest_file_name(timestamp, uuid))
+    }
+
+    /* sorted paths of manifests of all changesets of user */
+    pub fn manifest_files(&self, user_name: &str) -> Vec<PathBuf> {
+        let mut manifests: Vec<PathBuf> = WalkDir::new(self.changesets_dir(user_name, None))
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && e.path().to_str().unwrap_or("").ends_with(".manifest"))
+            .map(|e| e.path().to_path_buf())
+            .collect();
+        manifests.sort();
+        manifests
+    }
+
+    /* paths of all changeset part files of user, with domain they belong to. Includes parts of uncommitted writes */
+    pub fn changeset_files(&self, user_name: &str) -> Vec<(Option<String>, PathBuf)> {
+        let user_dir = self.user_dir(user_name);
+        let walker = WalkDir::new(&user_dir)
+            .follow_links(false)
+            .max_depth(4)
+            .max_open(256)
+            .into_iter();
+
+        walker
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().to_str().unwrap_or("").ends_with(".chset"))
+            .filter(|e| e.path().parent().and_then(|dir| dir.file_name()).and_then(|name| name.to_str()) == Some(CHANGESETS_DIR))
+            .map(|e| {
+                let domain = match e.path().parent().and_then(|dir| dir.parent()) {
+                    Some(dir) if dir != Path::new(&user_dir) => dir.file_name().and_then(|name| name.to_str()).map(|name| name.to_string()),
+                    _ => None, /* part of user */
+                };
+                (domain, e.path().to_path_buf())
+            })
+            .collect()
+    }
+
+    /* timestamp sorted changesets of user. Corrupted changesets are moved to quarantine, failure to read any of them is returned */
+    pub fn changesets(&self, user_name: &str) -> Result<Vec<Changeset>, String> {
+        self.assemble(user_name).map(|assembled| assembled.into_iter().map(|(changeset, _, _)| changeset).collect())
+    }
+
+    /* timestamp sorted committed changesets of user with their parts (relative to user dir) and size */
+    pub fn assemble(&self, user_name: &str) -> Result<Vec<(Changeset, Vec<String>, usize)>, String> {
+        info!("Reading changesets of user: {} from: {}", user_name, self.user_dir(user_name));
+        let mut changesets = vec!();
+        for manifest_path in self.manifest_files(user_name) {
+            match self.read_committed(user_name, &manifest_path) {
+                Ok(assembled) => {
+                    debug!("Decoded Changeset: {}", assembled.0);
+                    changesets.push(assembled);
+                },
+                Err(ReadError::Corrupted(err)) => {
+                    error!("Data processing failure: {}, while processing changeset: {:?}", err, manifest_path);
+                    match self.quarantine_changeset(user_name, &manifest_path) {
+                        Ok(target) => warn!("Corrupted changeset moved to: {}", target),
+                        Err(err) => error!("{}", err),
+                    }
+                },
+                Err(ReadError::Io(err)) => return Err(err),
+            }
+        }
+        changesets.sort_by(|a, b| a.0.timestamp.cmp(&b.0.timestamp)); /* sort changesets by timestamp */
+        Ok(changesets)
+    }
+
+    /* changeset assembled from all parts listed in its manifest, with these parts (relative to user dir) and size */
+    pub fn read_committed(&self, user_name: &str, manifest_path: &Path) -> Result<(Changeset, Vec<String>, usize), ReadError> {
+        let manifest = try!(read_manifest(manifest_path));
+        let user_dir = self.user_dir(user_name);
+        let mut assembled: Option<Changeset> = None;
+        let mut bytes = 0;
+        for part_file in manifest.parts.iter() {
+            let path = Path::new(&user_dir).join(part_file);
+            if !path.exists() {
+                return Err(ReadError::Corrupted(format!("Missing part: {:?} of changeset: {}", path, manifest.uuid)))
+            }
+            let part = try!(read_part(&path));
+            if part.uuid != manifest.uuid {
+                return Err(ReadError::Corrupted(format!("Part: {:?} doesn't belong to changeset: {}", path, manifest.uuid)))
+            }
+            bytes += path.metadata().map(|metadata| metadata.len() as usize).unwrap_or(0);
+            assembled = match assembled.take() {
+                Some(mut changeset) => {
+                    merge_part(&mut changeset, part);
+                    Some(changeset)
+                },
+                None => Some(part),
+            };
+        }
+        match assembled {
+            Some(mut changeset) => {
+                changeset.entries.sort_by(|a, b| a.name.cmp(&b.name));
+                changeset.removed.sort();
+                let mut parts = manifest.parts;
+                parts.sort();
+                Ok((changeset, parts, bytes))
+            },
+            None => Err(ReadError::Corrupted(format!("Manifest: {:?} lists no parts", manifest_path))),
+        }
+    }
+
+    /* removes manifest first, so interrupted removal leaves only parts that are never read */
+    pub fn remove_changeset(&self, user_name: &str, changeset: &Changeset) -> Result<(), String> {
+        let manifest_file = self.manifest_file(user_name, changeset.timestamp, changeset.uuid);
+        if Path::new(&manifest_file).exists() {
+            match remove_file(&manifest_file) {
+                Ok(_) => {},
+                Err(err) => return Err(format!("Failed to remove changeset: {}. Cause: {}", manifest_file, err)),
+            }
+        }
+        try!(self.remove_parts(user_name, changeset.timestamp, changeset.uuid, &[]));
+        self.unindex_changeset(user_name, changeset.uuid);
+        Ok(())
+    }
+
+    /* removes part files of given changeset, except kept ones */
+    fn remove_parts(&self, user_name: &str, timestamp: u64, uuid: Uuid, kept: &[String]) -> Result<(), String> {
+        let user_dir = self.user_dir(user_name);
+        let prefix = format!("{}-{}-", timestamp, uuid);
+        for (_, path) in self.changeset_files(user_name) {
+            let of_changeset = path.file_name().and_then(|name| name.to_str()).map(|name| name.starts_with(prefix.as_str())).unwrap_or(false);
+            let relative_path = path.strip_prefix(&user_dir).ok().and_then(|relative| relative.to_str()).unwrap_or("").to_string();
+            if of_changeset && !kept.contains(&relative_path) {
+                match remove_file(&path) {
+                    Ok(_) => {},
+                    Err(err) => return Err(format!("Failed to remove changeset: {:?}. Cause: {}", path, err)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        moves manifest and all parts of corrupted or incomplete changeset aside,
+        so it's reported by verify but never read as part of history
+     */
+    fn quarantine_changeset(&self, user_name: &str, manifest_path: &Path) -> Result<String, String> {
+        let name = match manifest_path.file_stem().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => return Err(format!("Invalid manifest file name: {:?}", manifest_path)),
+        };
+        let quarantine_dir = format!("{}/{}", self.quarantine_dir(user_name), name);
+        match create_dir_all(&quarantine_dir) {
+            Ok(_) => {},
+            Err(err) => return Err(format!("Failed to create dir: {}. Cause: {}", quarantine_dir, err)),
+        }
+        /* parts are found by name, so they are moved even if manifest can't be decoded */
+        let prefix = format!("{}-", name);
+        let mut files: Vec<(String, PathBuf)> = self.changeset_files(user_name)
+            .into_iter()
+            .filter(|&(_, ref path)| path.file_name().and_then(|name| name.to_str()).map(|name| name.starts_with(prefix.as_str())).unwrap_or(false))
+            .map(|(domain, path)| (domain.unwrap_or(String::from("user")), path))
+            .collect();
+        files.push((String::from("user"), manifest_path.to_path_buf()));
+        for (owner, path) in files {
+            let target = format!("{}/{}-{}", quarantine_dir, owner, path.file_name().and_then(|name| name.to_str()).unwrap_or(""));
+            match rename(&path, &target) {
+                Ok(_) => {},
+                Err(err) => return Err(format!("Failed to quarantine changeset: {:?}. Cause: {}", path, err)),
+            }
+        }
+        Ok(quarantine_dir)
+    }
+
+    /* one-time import of changesets stored by first releases in: LEGACY_DIR/USER_NAME/UUID-TIMESTAMP.chgset. Imported files are renamed to: *.imported */
+    pub fn import_legacy(&self, legacy_dir: &str, blobs_dir: &str, user_name: &str) -> Result<usize, String> {
+        let mut legacy_files: Vec<PathBuf> = WalkDir::new(format!("{}/{}", legacy_dir, user_name))
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && e.path().to_str().unwrap_or("").ends_with(".chgset"))
+            .map(|e| e.path().to_path_buf())
+            .collect();
+        legacy_files.sort();
+        let mut imported = 0;
+        for path in legacy_files {
+            let mut bytes = vec!();
+            match File::open(&path).and_then(|file| BufReader::new(file).read_to_end(&mut bytes)) {
+                Ok(_) => {},
+                Err(err) => return Err(format!("Failed to read legacy changeset: {:?}. Cause: {}", path, err)),
+            }
+            let suffix = match migrate_changeset(&bytes, blobs_dir) {
+                Ok(changeset) => {
+                    try!(self.store_changeset(user_name, &changeset));
+                    imported += 1;
+                    "imported"
+                },
+                Err(err) => {
+                    error!("Legacy changeset: {:?} can't be imported. Cause: {}", path, err);
+                    "corrupted"
+                },
+            };
+            let target = format!("{}.{}", path.to_str().unwrap_or(""), suffix);
+            match rename(&path, &target) {
+                Ok(_) => {},
+                Err(err) => return Err(format!("Failed to rename legacy changeset: {:?}. Cause: {}", path, err)),
+            }
+        }
+        if imported > 0 {
+            info!("Imported: {} legacy changesets of user: {}", imported, user_name);
+        }
+        Ok(imported)
+    }
+
+    /* file names of corrupted changesets of user */
+    pub fn quarantined_changesets(&self, user_name: &str) -> Vec<String> {
+        let mut files: Vec<String> = WalkDir::new(self.quarantine_dir(user_name))
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.path().to_str().map(|path| path.to_string()))
+            .collect();
+        files.sort();
+        files
+    }
+
+    pub fn state_file(&self, user_name: &str, domain: &str) -> String {
+        format!("{}/domains/{}.json", self.user_dir(user_name), domain)
+    }
+
+    /* replaces current state documents of user with domains of given materialized state */
+    pub fn store_domain_states(&self, user_name: &str, state: &Changeset) -> Result<usize, String> {
+        let domains_dir = format!("{}/domains", self.user_dir(user_name));
+        match create_dir_all(&domains_dir) {
+            Ok(_) => {},
+            Err(err) => return Err(format!("Failed to create dir: {}. Cause: {}", domains_dir, err)),
+        }
+        for entry in state.entries.iter() {
+            let domain_state = DomainState {
+                changeset: state.uuid,
+                timestamp: state.timestamp,
+                domain: entry.clone(),
+            };
+            let file_name = self.state_file(user_name, &entry.name);
+            match write_atomically(&file_name, domain_state.to_string().as_bytes()) {
+                Ok(_) => {},
+                Err(err) => return Err(format!("Failed to store state of domain: {}. Cause: {}", file_name, err)),
+            }
+        }
+        /* states of removed domains */
+        for (name, path) in self.state_files(user_name) {
+            if !state.entries.iter().any(|entry| entry.name == name) {
+                match remove_file(&path) {
+                    Ok(_) => debug!("Removed state of domain: {}", name),
+                    Err(err) => return Err(format!("Failed to remove state of domain: {:?}. Cause: {}", path, err)),
+                }
+            }
+        }
+        Ok(state.entries.len())
+    }
+
+    /* domain names with paths of their current state documents */
+    fn state_files(&self, user_name: &str) -> Vec<(String, PathBuf)> {
+        WalkDir::new(format!("{}/domains", self.user_dir(user_name)))
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                let name = e.file_name().to_str().unwrap_or("").to_string();
+                if name.ends_with(".json") {
+                    Some((name.trim_right_matches(".json").to_string(), e.path().to_path_buf()))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /* current state of domain, read from its single document */
+    pub fn domain_state(&self, user_name: &str, domain: &str) -> Option<DomainState> {
+        read_domain_state(Path::new(&self.state_file(user_name, domain)))
+    }
+
+    /* current states of all domains of user, sorted by domain name */
+    pub fn domain_states(&self, user_name: &str) -> Vec<DomainState> {
+        let mut states: Vec<DomainState> = self.state_files(user_name)
+            .into_iter()
+            .filter_map(|(_, path)| read_domain_state(&path))
+            .collect();
+        states.sort_by(|a, b| a.domain.name.cmp(&b.domain.name));
+        states
+    }
+}
+
+
+/* host, user and domain names are used to build store paths, so only plain names are accepted */
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 255 && !name.starts_with('.') && name.chars().all(|character| {
+        match character {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '.' | '-' | '_' => true,
+            _ => false,
+        }
+    })
+}
+
+
+fn read_domain_state(path: &Path) -> Option<DomainState> {
+    let mut content = String::new();
+    let read = File::open(path).and_then(|file| BufReader::new(file).read_to_string(&mut content));
+    match read {
+        Ok(_) => match json::decode(&content) {
+            Ok(state) => Some(state),
+            Err(err) => {
+                error!("Failed to decode state of domain: {:?}. Cause: {}", path, err);
+                None
+            },
+        },
+        Err(err) => {
+            debug!("No state of domain: {:?}. Cause: {}", path, err);
+            None
+        },
+    }
+}
+
+
+/* writes temporary file, then renames it, so readers never see partially written content. Concurrent writers of same file use own temporary files */
+pub fn write_atomically(file_name: &str, bytes: &[u8]) -> io::Result<()> {
+    let temp_file = format!("{}.{}.tmp", file_name, Uuid::new_v4());
+    let written = File::create(&temp_file)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            try!(writer.write_all(bytes));
+            match writer.into_inner() {
+                Ok(file) => file.sync_all(),
+                Err(err) => Err(err.into()),
+            }
+        })
+        .and_then(|_| rename(&temp_file, file_name));
+    if written.is_err() {
+        remove_file(&temp_file).unwrap_or(());
+    }
+    written
+}
+
+
+/* name of part file is unique for each write, so rewritten changeset never replaces parts of committed one */
+fn part_file_name(changeset: &Changeset) -> String {
+    format!("{}-{}-{}.chset", changeset.timestamp, changeset.uuid, Uuid::new_v4())
+}
+
+
+pub fn manifest_file_name(timestamp: u64, uuid: Uuid) -> String {
+    format!("{}-{}.manifest", timestamp, uuid)
+}
+
+
+fn read_manifest(path: &Path) -> Result<Manifest, ReadError> {
+    let mut content = String::new();
+    let read = File::open(path).and_then(|file| BufReader::new(file).read_to_string(&mut content));
+    match read {
+        Ok(_) => json::decode(&content).map_err(|err| ReadError::Corrupted(format!("Failed to decode manifest: {:?}. Cause: {}", path, err))),
+        Err(err) => Err(ReadError::Io(format!("Failed to read manifest: {:?}. Cause: {}", path, err))),
+    }
+}
+
+
+fn sync_dir(dir: &str) -> io::Result<()> {
+    File::open(dir).and_then(|dir| dir.sync_all())
+}
+
+
+/* removes parts of changeset that failed to be stored */
+fn remove_written(written: &[(String, String)]) {
+    for &(_, ref target) in written.iter() {
+        remove_file(target).unwrap_or(());
+    }
+}
+
+
+/* single stored part of changeset */
+fn read_part(path: &Path) -> Result<Changeset, ReadError> {
+    let mut bytes = vec!();
+    let read = File::open(path).and_then(|file| BufReader::new(file).read_to_end(&mut bytes));
+    match read {
+        Ok(_) => decode_changeset(&bytes).map_err(ReadError::Corrupted),
+        Err(err) => Err(ReadError::Io(format!("Failed to read changeset: {:?}. Cause: {}", path, err))),
+    }
+}
+
+
+fn merge_part(changeset: &mut Changeset, part: Changeset) {
+    changeset.entries.extend(part.entries);
+    changeset.removed.extend(part.removed);
+}
+
+
+/* changeset split by domain. Changeset without any domain changes is kept as single part of user */
+fn parts_of(changeset: &Changeset) -> Vec<(Option<String>, Changeset)> {
+    let header = Changeset { entries: vec!(), removed: vec!(), .. changeset.clone() };
+    let mut parts = vec!();
+    for entry in changeset.entries.iter() {
+        parts.push((Some(entry.name.clone()), Changeset { entries: vec!(entry.clone()), .. header.clone() }));
+    }
+    for name in changeset.removed.iter() {
+        parts.push((Some(name.clone()), Changeset { removed: vec!(name.clone()), .. header.clone() }));
+    }
+    if parts.is_empty() {
+        parts.push((None, header));
+    }
+    parts
+}
+
+
+#[cfg(test)]
+#[test]
+fn spice_store_test() {
+    use std::fs::read_dir;
+    use std::thread;
+
+    let root = "/tmp/yak-store-test";
+    remove_dir_all(root).unwrap_or(());
+    let store = SpiceStore::new(root, "s1");
+
+    let domain = |name: &str| DomainEntry { name: name.to_string(), files: vec!(FileEntry { path: format!("/{}/index.php", name), .. Default::default() }), .. Default::default() };
+    let first = Changeset { timestamp: 1000, entries: vec!(domain("b.com"), domain("a.com")), .. Default::default() };
+    let second = Changeset { timestamp: 2000, parent: first.uuid, full: false, removed: vec!(String::from("b.com")), .. Default::default() };
+    let third = Changeset { timestamp: 3000, parent: second.uuid, full: false, .. Default::default() };
+    for changeset in vec!(&first, &second, &third) {
+        store.store_changeset("admin6", changeset).unwrap();
+    }
+    let part_of = |domain: Option<&str>, changeset: &Changeset| store.changeset_files("admin6")
+        .into_iter()
+        .find(|&(ref owner, ref path)| owner.as_ref().map(|name| name.as_str()) == domain && path.to_str().unwrap().contains(changeset.uuid.to_string().as_str()))
+        .map(|(_, path)| path);
+    assert!(part_of(Some("a.com"), &first).is_some() && part_of(Some("b.com"), &second).is_some() && part_of(None, &third).is_some());
+    assert!(Path::new(&format!("{}/s1/admin6/.chsets/1000-{}.manifest", root, first.uuid)).exists());
+    assert!(store.users() == vec!(String::from("admin6")));
+    assert!(SpiceStore::new(root, "s2").changesets("admin6").unwrap().is_empty(), "Changesets of other host shouldn't be visible");
+
+    /* concurrent writers of same file, like scan and reindex of same user */
+    let shared = format!("{}/shared.json", root);
+    let writers: Vec<_> = (0..8u8).map(|writer| {
+        let shared = shared.clone();
+        thread::spawn(move || write_atomically(&shared, &vec![writer; 65536]).is_ok())
+    }).collect();
+    assert!(writers.into_iter().all(|writer| writer.join().unwrap()), "Writers shouldn't replace temporary files of each other");
+    let mut written = vec!();
+    File::open(&shared).unwrap().read_to_end(&mut written).unwrap();
+    assert!(written.len() == 65536 && written.iter().all(|byte| *byte == written[0]));
+
+    let changesets = store.changesets("admin6").unwrap();
+    assert!(changesets.len() == 3);
+    assert!(changesets[0].uuid == first.uuid && changesets[0].entries.len() == 2 && changesets[0].entries[0].name == "a.com");
+    assert!(changesets[1].removed == vec!(String::from("b.com")) && changesets[1].entries.is_empty());
+    assert!(changesets[2].uuid == third.uuid && !changesets[2].full);
+
+    /* parts without manifest are leftovers of interrupted write */
+    let uncommitted = Changeset { timestamp: 4000, parent: third.uuid, full: false, .. Default::default() };
+    store.store_changeset("admin6", &uncommitted).unwrap();
+    remove_file(&store.manifest_file("admin6", uncommitted.timestamp, uncommitted.uuid)).unwrap();
+    assert!(store.changesets("admin6").unwrap().len() == 3);
+    assert!(part_of(None, &uncommitted).is_some());
+
+    /* truncated part of changeset, its other parts are quarantined with it */
+    let broken_file = part_of(Some("b.com"), &first).unwrap();
+    let mut bytes = vec!();
+    File::open(&broken_file).unwrap().read_to_end(&mut bytes).unwrap();
+    File::create(&broken_file).unwrap().write_all(&bytes[..bytes.len() / 2]).unwrap();
+    let changesets = store.changesets("admin6").unwrap();
+    assert!(changesets.len() == 2 && changesets[0].uuid == second.uuid);
+    assert!(store.quarantined_changesets("admin6").len() == 1);
+    assert!(part_of(Some("a.com"), &first).is_none(), "All parts of corrupted changeset should be quarantined");
+    assert!(read_dir(format!("{}/s1/admin6/quarantine/1000-{}", root, first.uuid)).unwrap().count() == 3);
+
+    /* part listed in manifest is gone */
+    remove_file(part_of(None, &third).unwrap()).unwrap();
+    assert!(store.changesets("admin6").unwrap().len() == 1);
+    assert!(store.quarantined_changesets("admin6").len() == 2);
+
+    /* rewritten changeset supersedes parts stored before */
+    let rewritten = Changeset { full: true, .. second.clone() };
+    store.store_changeset("admin6", &rewritten).unwrap();
+    assert!(store.changesets("admin6").unwrap()[0].full);
+    assert!(store.changeset_files("admin6").iter().filter(|&&(_, ref path)| path.to_str().unwrap().contains(second.uuid.to_string().as_str())).count() == 1);
+
+    /* part that can't be read isn't corrupted, so it's reported instead of quarantined */
+    let unreadable = part_of(Some("b.com"), &second).unwrap();
+    remove_file(&unreadable).unwrap();
+    create_dir_all(&unreadable).unwrap();
+    assert!(store.changesets("admin6").is_err());
+    assert!(store.quarantined_changesets("admin6").len() == 2);
+    remove_dir_all(&unreadable).unwrap();
+    store.remove_changeset("admin6", &second).unwrap();
+    assert!(store.changesets("admin6").unwrap().is_empty());
+
+    for valid in vec!("s1", "admin6", "example.com", "my-domain.co.uk", "user_1") {
+        assert!(valid_name(valid), valid);
+    }
+    for invalid in vec!("", ".", "..", "../etc", "a/b", ".hidden", "a b", "zażółć.pl", "s1%2f..") {
+        assert!(!valid_name(invalid), invalid);
+    }
+
+    store.store_domain_states("admin6", &first).unwrap();
+    assert!(Path::new(&format!("{}/s1/admin6/domains/b.com.json", root)).exists());
+    let state = Changeset { entries: vec!(domain("a.com")), .. third.clone() };
+    assert!(store.store_domain_states("admin6", &state) == Ok(1));
+    assert!(!Path::new(&format!("{}/s1/admin6/domains/b.com.json", root)).exists(), "State of removed domain should be removed");
+    let current = store.domain_state("admin6", "a.com").unwrap();
+    assert!(current.changeset == third.uuid && current.domain.files[0].path == "/a.com/index.php");
+    assert!(store.domain_states("admin6").len() == 1);
+    assert!(store.domain_state("admin6", "b.com").is_none());
+}
+
+
+#[cfg(test)]
+#[test]
+fn import_legacy_test() {
+    use format::encode_v1;
+    use blobs::load_blob;
+
+    let root = "/tmp/yak-legacy-test";
+    remove_dir_all(root).unwrap_or(());
+    let legacy_dir = format!("{}/.changesets", root);
+    let blobs_dir = format!("{}/blobs", root);
+    create_dir_all(format!("{}/admin6", legacy_dir)).unwrap();
+    let (uuid, timestamp) = (Uuid::new_v4(), 1464000000000);
+    let path = "/home/admin6/domains/example.com/public_html/index.php";
+    File::create(format!("{}/admin6/{}-{}.chgset", legacy_dir, uuid, timestamp)).unwrap()
+        .write_all(&encode_v1(uuid, timestamp, "example.com", path, b"<?php echo 1;")).unwrap();
+    File::create(format!("{}/admin6/{}-1.chgset", legacy_dir, Uuid::new_v4())).unwrap().write_all(b"garbage").unwrap();
+
+    let store = SpiceStore::new(&format!("{}/store", root), "s1");
+    assert!(store.import_legacy(&legacy_dir, &blobs_dir, "admin6") == Ok(1));
+    let changesets = store.changesets("admin6").unwrap();
+    assert!(changesets.len() == 1 && changesets[0].uuid == uuid && changesets[0].timestamp == timestamp);
+    let file = &changesets[0].entries[0].files[0];
+    assert!(changesets[0].entries[0].name == "example.com" && file.path == path);
+    assert!(load_blob(&blobs_dir, &file.content_sha256).unwrap() == b"<?php echo 1;".to_vec());
+    assert!(Path::new(&format!("{}/admin6/{}-{}.chgset.imported", legacy_dir, uuid, timestamp)).exists());
+    assert!(store.import_legacy(&legacy_dir, &blobs_dir, "admin6") == Ok(0), "Changesets should be imported once");
+    assert!(store.changesets("admin6").unwrap().len() == 1);
+    assert!(store.import_legacy(&legacy_dir, &blobs_dir, "nobody") == Ok(0));
+}
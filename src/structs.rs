@@ -8,6 +8,9 @@ use rustc_serialize::{Encodable, json};
 
 use base::*;
 use time::*;
+use integrity::FsDiff;
+use signatures::SignatureHit;
+use probe::HttpProbe;
 
 
 #[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
@@ -32,22 +35,40 @@ pub struct Owner {
 #[derive(RustcDecodable, RustcEncodable, Clone)]
 pub struct FileEntry {
     pub path: String,
-    pub sha1: String,
-    pub local_content: Vec<u8>,
+    pub text_sha1: String, /* checksum of normalized text, used for language and similarity detection */
+    pub raw_sha1: String, /* checksums of whole, unmodified file content */
+    pub raw_sha256: String,
+    pub signature_hits: Vec<SignatureHit>, /* malware signatures matched in file content */
+    pub suspicion: u32, /* heuristic score of code obfuscation: 0 - 100 */
+    pub content_sha256: String, /* key of local content in blob store */
     pub lang: String,
     pub encoding: String,
     pub owner: Owner,
     pub size: u64,
     pub mode: u32,
     pub modified: i64,
+    pub mtime: i64, /* modification time and inode are used to detect unchanged files in incremental scans */
+    pub inode: u64,
 }
 
 
+/* single domain of user with all files scanned in its document root */
 #[derive(RustcDecodable, RustcEncodable, Clone)]
 pub struct DomainEntry {
     pub name: String,
-    pub request_path: String,
-    pub file: FileEntry,
+    pub docroot: String,
+    pub files: Vec<FileEntry>,
+    pub removed_files: Vec<String>, /* paths of files removed since parent changeset */
+    pub app_type: Option<WebAppTypes>,
+    pub version: String,
+    pub interpreter: String, /* minimal interpreter version required by web app, if known */
+    pub environment: Vec<String>, /* modified interpreter environment values */
+    pub outdated: bool,
+    pub zombie: bool, /* web app version no longer supported by its devs */
+    pub fs_diffs: Vec<FsDiff>, /* framework files that differ from pristine release */
+    pub fs_suspicious: Vec<String>, /* files with signature hits or suspicion score above threshold */
+    pub encodings: Vec<String>, /* distinct encodings of scanned files */
+    pub states: Vec<DomainStates>, /* health of whole domain, most important first */
 
     pub http_content: String,
     pub http_content_encoding: String,
@@ -61,6 +82,7 @@ pub struct DomainEntry {
     pub https_status_code: u32,
     pub https_response_time: u64,
 
+    pub probes: Vec<HttpProbe>, /* probes of additional paths from config */
 }
 
 
@@ -69,6 +91,9 @@ pub struct Changeset {
     pub uuid: Uuid,
     pub parent: Uuid,
     pub timestamp: u64,
+    pub severity: SeverityLevels, /* level used by scan that produced this changeset */
+    pub full: bool, /* full snapshot, otherwise only changes since parent changeset */
+    pub removed: Vec<String>, /* names of domains removed since parent changeset */
     pub entries: Vec<DomainEntry>,
 }
 
@@ -79,6 +104,9 @@ impl Default for Changeset {
             uuid: Uuid::new_v4(),
             parent: root_uuid(),
             timestamp: precise_time_ns() / 1000 / 1000,
+            severity: SeverityLevels::Normal,
+            full: true,
+            removed: vec!(),
             entries: Vec::new(),
         }
     }
@@ -102,8 +130,12 @@ impl Default for FileEntry {
     fn default() -> FileEntry {
         FileEntry {
             path: String::new(),
-            sha1: String::new(),
-            local_content: vec!(),
+            text_sha1: String::new(),
+            raw_sha1: String::new(),
+            raw_sha256: String::new(),
+            signature_hits: vec!(),
+            suspicion: 0,
+            content_sha256: String::new(),
             lang: String::new(),
             encoding: String::new(),
             size: 0,
@@ -116,6 +148,8 @@ impl Default for FileEntry {
             },
             mode: 0,
             modified: 0,
+            mtime: 0,
+            inode: 0,
         }
     }
 }
@@ -135,8 +169,19 @@ impl Default for DomainEntry {
     fn default() -> DomainEntry {
         DomainEntry {
             name: String::from("localhost"),
-            request_path: String::from("/"),
-            file: FileEntry { .. Default::default() },
+            docroot: String::new(),
+            files: vec!(),
+            removed_files: vec!(),
+            app_type: None,
+            version: String::new(),
+            interpreter: String::new(),
+            environment: vec!(),
+            outdated: false,
+            zombie: false,
+            fs_diffs: vec!(),
+            fs_suspicious: vec!(),
+            encodings: vec!(),
+            states: vec!(),
             http_content: String::new(),
             http_content_encoding: String::new(),
             http_content_size: 0,
@@ -147,6 +192,7 @@ impl Default for DomainEntry {
             https_content_size: 0,
             https_status_code: 0,
             https_response_time: 0,
+            probes: vec!(),
         }
     }
 }
@@ -182,7 +228,16 @@ impl Display for DomainEntry {
 }
 
 
-enum DomainStates {
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub enum SeverityLevels {
+    Normal,     /* default mode */
+    Pedantic,   /* Warning is Suspected. Empty, Unresolvable and Unknown are Broken */
+    Psycho,     /* Pedantic, Suspected and Malicious are Hacked. Changesets keep full information */
+}
+
+
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub enum DomainStates {
     Ok,
     Warning,
     Suspected,
@@ -194,7 +249,8 @@ enum DomainStates {
     Unknown,
 }
 
-enum WebAppTypes {
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub enum WebAppTypes {
     WordPress,
     Joomla,
     Prestashop,
@@ -5,6 +5,10 @@ use std::collections::HashSet;
 use users::{User, AllUsers};
 use regex::Regex;
 use sha1;
+use std::io;
+use std::io::prelude::Write;
+use openssl::crypto::hash::{Hasher, Type};
+use rustc_serialize::hex::ToHex;
 use encoding::types::*;
 use term;
 use difference::diff;
@@ -54,6 +58,42 @@ pub fn sha1_of(input: String) -> String {
 }
 
 
+/* streams whole content through SHA-1 and SHA-256, returns both hex digests */
+pub fn raw_checksums_of<R>(mut reader: R) -> io::Result<(String, String)> where R: Read {
+    let mut sha1 = sha1::Sha1::new();
+    let mut sha256 = Hasher::new(Type::SHA256);
+    let mut buf = [0u8; 65536];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(bytes) => {
+                sha1.update(&buf[..bytes]);
+                try!(sha256.write_all(&buf[..bytes]));
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok((sha1.hexdigest(), sha256.finish().to_hex()))
+}
+
+
+#[cfg(test)]
+#[test]
+fn raw_checksums_of_test() {
+    let (sha1, sha256) = raw_checksums_of("abc".as_bytes()).unwrap();
+    assert!(sha1 == "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert!(sha256 == "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+    /* content that differs only past the usual read limit must not hash the same */
+    let mut content = vec![b'a'; 131070];
+    let (prefix_sha1, _) = raw_checksums_of(&content[..]).unwrap();
+    content.extend_from_slice(b"<?php eval($_POST['x']);");
+    let (full_sha1, _) = raw_checksums_of(&content[..]).unwrap();
+    assert!(prefix_sha1 != full_sha1);
+}
+
+
 /* html tag cleaner PoC: */
 pub fn strip_html_tags(binary_content: &Vec<u8>) -> String {
     let a_buf = String::from_utf8_lossy(&binary_content);
@@ -0,0 +1,313 @@
+use process::*;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+
+/*
+    Marker files (relative to domain document root) that identify installed web application.
+    All markers of a set must exist. Sets are checked in order, so more specific ones go first.
+ */
+pub fn webapp_markers() -> Vec<(WebAppTypes, Vec<&'static str>)> {
+    vec!(
+        (WebAppTypes::WordPress, vec!("wp-includes/version.php", "wp-login.php")),
+        (WebAppTypes::Joomla, vec!("configuration.php", "libraries/joomla")),
+        (WebAppTypes::Mamboo, vec!("configuration.php", "mambots")),
+        (WebAppTypes::Magento, vec!("app/Mage.php")),
+        (WebAppTypes::Magento, vec!("app/etc/env.php", "bin/magento")), /* Magento 2 */
+        (WebAppTypes::Drupal, vec!("core/lib/Drupal.php")),
+        (WebAppTypes::Drupal, vec!("includes/bootstrap.inc", "misc/drupal.js")),
+        (WebAppTypes::Prestashop, vec!("config/settings.inc.php", "config/defines.inc.php", "classes")),
+        (WebAppTypes::Phpbb, vec!("includes/constants.php", "viewtopic.php")),
+        (WebAppTypes::Moodle, vec!("version.php", "lib/moodlelib.php")),
+        (WebAppTypes::Owncloud, vec!("version.php", "status.php", "ocs/v1.php")),
+        (WebAppTypes::Mybb, vec!("inc/class_core.php", "member.php")),
+        (WebAppTypes::CmsMadeSimple, vec!("include.php", "lib/cmsms.api.php")),
+        (WebAppTypes::PhpFusion, vec!("maincore.php", "infusions")),
+        (WebAppTypes::Zurmo, vec!("app/protected/modules/zurmo")),
+        (WebAppTypes::Phpmyadmin, vec!("libraries/common.inc.php", "db_structure.php")),
+        (WebAppTypes::Afterlogic, vec!("libraries/afterlogic")),
+        (WebAppTypes::Squirrelmail, vec!("functions/imap.php", "src/login.php")),
+        (WebAppTypes::Roundcube, vec!("program/include/iniset.php")),
+        (WebAppTypes::Limesurvey, vec!("application/config/version.php", "admin")),
+        (WebAppTypes::ZenCart, vec!("includes/version.php", "ipn_main_handler.php")),
+        (WebAppTypes::Piwik, vec!("core/Version.php", "piwik.php")),
+        (WebAppTypes::PHPList, vec!("lists/admin/init.php")),
+        (WebAppTypes::PHPList, vec!("admin/init.php", "admin/commonlib")),
+    )
+}
+
+
+pub fn all_webapp_types() -> Vec<WebAppTypes> {
+    vec!(
+        WebAppTypes::WordPress, WebAppTypes::Joomla, WebAppTypes::Prestashop, WebAppTypes::Phpbb,
+        WebAppTypes::Magento, WebAppTypes::Moodle, WebAppTypes::Owncloud, WebAppTypes::Drupal,
+        WebAppTypes::Mybb, WebAppTypes::CmsMadeSimple, WebAppTypes::PhpFusion, WebAppTypes::Zurmo,
+        WebAppTypes::Phpmyadmin, WebAppTypes::Afterlogic, WebAppTypes::Squirrelmail, WebAppTypes::Roundcube,
+        WebAppTypes::Limesurvey, WebAppTypes::ZenCart, WebAppTypes::Piwik, WebAppTypes::PHPList,
+        WebAppTypes::Mamboo, WebAppTypes::CustomX,
+    )
+}
+
+
+/* case insensitive lookup of web app type by its name, f.e.: "wordpress" */
+pub fn webapp_type_of(name: &str) -> Option<WebAppTypes> {
+    all_webapp_types()
+        .into_iter()
+        .find(|app_type| format!("{:?}", app_type).to_lowercase() == name.to_lowercase())
+}
+
+
+/* detects web application installed in given document root */
+pub fn detect_webapp(docroot: &str) -> Option<WebAppTypes> {
+    let root = Path::new(docroot);
+    for (app_type, markers) in webapp_markers() {
+        if markers.iter().all(|marker| root.join(marker).exists()) {
+            debug!("Detected web app: {:?} in: {}", app_type, docroot);
+            return Some(app_type)
+        }
+    }
+    /* something is served, but it's nothing we know */
+    if root.join("index.php").exists() {
+        debug!("Detected custom web app in: {}", docroot);
+        Some(WebAppTypes::CustomX)
+    } else {
+        None
+    }
+}
+
+
+/*
+    Version sources of web applications: file relative to document root and list of patterns.
+    Each pattern captures single version component, components are joined with dots.
+ */
+pub fn version_sources() -> Vec<(WebAppTypes, &'static str, Vec<&'static str>)> {
+    vec!(
+        (WebAppTypes::WordPress, "wp-includes/version.php", vec!(r"\$wp_version\s*=\s*'([^']+)'")),
+        (WebAppTypes::Joomla, "libraries/cms/version/version.php", vec!(r"RELEASE\s*=\s*'([^']+)'", r"DEV_LEVEL\s*=\s*'([^']+)'")),
+        (WebAppTypes::Joomla, "libraries/joomla/version.php", vec!(r"RELEASE\s*=\s*'([^']+)'", r"DEV_LEVEL\s*=\s*'([^']+)'")),
+        (WebAppTypes::Mamboo, "includes/version.php", vec!(r"RELEASE\s*=\s*'([^']+)'", r"DEV_LEVEL\s*=\s*'([^']+)'")),
+        (WebAppTypes::Magento, "app/Mage.php", vec!(r"'major'\s*=>\s*'(\d+)'", r"'minor'\s*=>\s*'(\d+)'", r"'revision'\s*=>\s*'(\d+)'", r"'patch'\s*=>\s*'(\d+)'")),
+        (WebAppTypes::Magento, "composer.json", vec!(r#""version":\s*"([^"]+)""#)), /* Magento 2 */
+        (WebAppTypes::Drupal, "core/lib/Drupal.php", vec!(r"const VERSION\s*=\s*'([^']+)'")),
+        (WebAppTypes::Drupal, "includes/bootstrap.inc", vec!(r"define\('VERSION',\s*'([^']+)'\)")),
+        (WebAppTypes::Prestashop, "config/settings.inc.php", vec!(r"define\('_PS_VERSION_',\s*'([^']+)'\)")),
+        (WebAppTypes::Phpbb, "includes/constants.php", vec!(r"define\('PHPBB_VERSION',\s*'([^']+)'\)")),
+        (WebAppTypes::Moodle, "version.php", vec!(r"\$release\s*=\s*'([0-9.]+)")),
+        (WebAppTypes::Owncloud, "version.php", vec!(r"\$OC_VersionString\s*=\s*'([^']+)'")),
+        (WebAppTypes::Mybb, "inc/class_core.php", vec!(r#"\$version\s*=\s*"([^"]+)""#)),
+        (WebAppTypes::Phpmyadmin, "libraries/Config.class.php", vec!(r"'PMA_VERSION',\s*'([^']+)'")),
+        (WebAppTypes::Roundcube, "program/include/iniset.php", vec!(r"define\('RCMAIL_VERSION',\s*'([^']+)'\)")),
+        (WebAppTypes::Squirrelmail, "functions/strings.php", vec!(r"\$version\s*=\s*'([^']+)'")),
+        (WebAppTypes::Limesurvey, "application/config/version.php", vec!(r"\$config\['versionnumber'\]\s*=\s*'([^']+)'")),
+        (WebAppTypes::ZenCart, "includes/version.php", vec!(r"PROJECT_VERSION_MAJOR',\s*'([^']+)'", r"PROJECT_VERSION_MINOR',\s*'([^']+)'")),
+        (WebAppTypes::Piwik, "core/Version.php", vec!(r"const VERSION\s*=\s*'([^']+)'")),
+    )
+}
+
+
+/* extracts version of given web application from its own version file */
+pub fn detect_version(app_type: &WebAppTypes, docroot: &str) -> Option<String> {
+    lazy_static! {
+        static ref SOURCES: Vec<(WebAppTypes, &'static str, Vec<Regex>)> = version_sources()
+            .into_iter()
+            .map(|(app_type, file, patterns)| (app_type, file, patterns.into_iter().map(|pattern| Regex::new(pattern).unwrap()).collect()))
+            .collect();
+    }
+    for &(ref source_type, file, ref patterns) in SOURCES.iter() {
+        if *source_type != *app_type {
+            continue
+        }
+        let path = Path::new(docroot).join(file);
+        let content = match File::open(&path) {
+            Ok(file) => match read_fragment(BufReader::new(file), config().read_limit) {
+                Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                None => continue,
+            },
+            Err(_) => continue,
+        };
+        let mut components = vec!();
+        for regex in patterns.iter() {
+            match regex.captures(&content).and_then(|captures| captures.at(1).map(|e| e.to_string())) {
+                Some(component) => components.push(component),
+                None => break,
+            }
+        }
+        if !components.is_empty() {
+            let version = components.join(".");
+            debug!("Detected version: {} of web app: {:?} in: {}", version, app_type, docroot);
+            return Some(version)
+        }
+        warn!("No version found in: {:?}", path);
+    }
+    None
+}
+
+
+/* compares dotted versions by numeric components, f.e.: "4.10" > "4.9.1" */
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let numbers = |version: &str| -> Vec<u64> {
+        version
+            .split(|c: char| c == '.' || c == '-' || c == '_')
+            .map(|component| {
+                let digits: String = component.chars().take_while(|c| c.is_digit(10)).collect();
+                digits.parse::<u64>().unwrap_or(0)
+            })
+            .collect()
+    };
+    let (mut a_numbers, mut b_numbers) = (numbers(a), numbers(b));
+    let length = if a_numbers.len() > b_numbers.len() { a_numbers.len() } else { b_numbers.len() };
+    a_numbers.resize(length, 0);
+    b_numbers.resize(length, 0);
+    a_numbers.cmp(&b_numbers)
+}
+
+
+/* entry of releases table, stored in JSON file keyed by web app type name */
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone, PartialEq)]
+pub struct Release {
+    pub latest: Vec<String>, /* newest release of each maintained branch */
+    pub supported_since: String, /* anything older is a zombie with no support from devs */
+    pub supported: bool, /* if false, then whole app is a zombie */
+}
+
+
+pub fn parse_releases(content: &str) -> Result<BTreeMap<String, Release>, String> {
+    match json::decode(content) {
+        Ok(releases) => Ok(releases),
+        Err(err) => Err(format!("Releases table parse failure: {}", err)),
+    }
+}
+
+
+pub fn load_releases(path: &str) -> BTreeMap<String, Release> {
+    let mut content = String::new();
+    match File::open(path) {
+        Ok(file) => {
+            match BufReader::new(file).read_to_string(&mut content) {
+                Ok(_) => {},
+                Err(err) => {
+                    error!("Failed to read releases table: {}. Cause: {}", path, err);
+                    return BTreeMap::new()
+                },
+            }
+        },
+        Err(err) => {
+            warn!("No releases table: {}. Outdated checks disabled. Cause: {}", path, err);
+            return BTreeMap::new()
+        },
+    }
+    match parse_releases(&content) {
+        Ok(releases) => releases,
+        Err(err) => {
+            error!("{}", err);
+            BTreeMap::new()
+        },
+    }
+}
+
+
+/* returns (outdated, zombie) of given web app version */
+pub fn check_release(app_type: &WebAppTypes, version: &str, releases: &BTreeMap<String, Release>) -> (bool, bool) {
+    let release = match releases.get(&format!("{:?}", app_type)) {
+        Some(release) => release,
+        None => return (false, false),
+    };
+    /* branch of release is its "major.minor" or at least "major" version prefix */
+    let branch = |version: &str, depth: usize| version.split('.').take(depth).collect::<Vec<&str>>().join(".");
+    let same_branch = |depth: usize| release.latest.iter().find(|latest| branch(latest.as_str(), depth) == branch(version, depth));
+    let newest = release.latest.iter().max_by_key(|latest| {
+        latest.split('.').map(|e| e.parse::<u64>().unwrap_or(0)).collect::<Vec<u64>>()
+    });
+    let outdated = match same_branch(2).or(same_branch(1)).or(newest) {
+        Some(latest) => compare_versions(version, latest) == Ordering::Less,
+        None => false,
+    };
+    let zombie = !release.supported || (!release.supported_since.is_empty() &&
+        compare_versions(version, &release.supported_since) == Ordering::Less);
+    (outdated || zombie, zombie)
+}
+
+
+#[cfg(test)]
+#[test]
+fn detect_webapp_test() {
+    let root = "/tmp/yak-webapps";
+    remove_dir_all(Path::new(root)).unwrap_or(());
+    for (dir, files) in vec!(
+        ("wordpress", vec!("wp-includes/version.php", "wp-login.php", "index.php")),
+        ("magento", vec!("app/Mage.php", "index.php")),
+        ("magento2", vec!("app/etc/env.php", "bin/magento", "index.php")),
+        ("drupal8", vec!("core/lib/Drupal.php")),
+        ("custom", vec!("index.php")),
+        ("static", vec!("index.html")),
+    ) {
+        for file in files {
+            let path = Path::new(root).join(dir).join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+    }
+    assert!(detect_webapp(&format!("{}/wordpress", root)) == Some(WebAppTypes::WordPress));
+    assert!(detect_webapp(&format!("{}/magento", root)) == Some(WebAppTypes::Magento));
+    assert!(detect_webapp(&format!("{}/magento2", root)) == Some(WebAppTypes::Magento));
+    assert!(detect_webapp(&format!("{}/drupal8", root)) == Some(WebAppTypes::Drupal));
+    assert!(detect_webapp(&format!("{}/custom", root)) == Some(WebAppTypes::CustomX));
+    assert!(detect_webapp(&format!("{}/static", root)) == None);
+    assert!(detect_webapp(&format!("{}/nonexistent", root)) == None);
+
+    assert!(webapp_type_of("wordpress") == Some(WebAppTypes::WordPress));
+    assert!(webapp_type_of("ZENCART") == Some(WebAppTypes::ZenCart));
+    assert!(webapp_type_of("Typo3") == None);
+}
+
+
+#[cfg(test)]
+#[test]
+fn detect_version_test() {
+    let root = "/tmp/yak-webapps-versions";
+    remove_dir_all(Path::new(root)).unwrap_or(());
+    for (file, content) in vec!(
+        ("wordpress/wp-includes/version.php", "<?php\n$wp_db_version = 36686;\n$wp_version = '4.5.2';\n"),
+        ("joomla/libraries/cms/version/version.php", "<?php\nclass JVersion {\n\tconst RELEASE = '3.5';\n\tconst DEV_LEVEL = '1';\n}"),
+        ("magento/app/Mage.php", "'major' => '1',\n'minor' => '9',\n'revision' => '2',\n'patch' => '4',\n"),
+        ("magento2/composer.json", "{\n    \"name\": \"magento/magento2ce\",\n    \"version\": \"2.1.0\",\n    \"license\": [\"OSL-3.0\"]\n}"),
+        ("broken/wp-includes/version.php", "<?php // nothing here"),
+    ) {
+        let path = Path::new(root).join(file);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+    }
+    assert!(detect_version(&WebAppTypes::WordPress, &format!("{}/wordpress", root)) == Some(String::from("4.5.2")));
+    assert!(detect_version(&WebAppTypes::Joomla, &format!("{}/joomla", root)) == Some(String::from("3.5.1")));
+    assert!(detect_version(&WebAppTypes::Magento, &format!("{}/magento", root)) == Some(String::from("1.9.2.4")));
+    assert!(detect_version(&WebAppTypes::Magento, &format!("{}/magento2", root)) == Some(String::from("2.1.0")));
+    assert!(detect_version(&WebAppTypes::WordPress, &format!("{}/broken", root)) == None);
+}
+
+
+#[cfg(test)]
+#[test]
+fn check_release_test() {
+    assert!(compare_versions("4.10", "4.9.1") == Ordering::Greater);
+    assert!(compare_versions("4.5", "4.5.0") == Ordering::Equal);
+    assert!(compare_versions("3.0.4+", "3.1") == Ordering::Less);
+
+    let releases = parse_releases(r#"{
+        "WordPress": {"latest": ["4.5.3"], "supported_since": "4.5", "supported": true},
+        "Drupal": {"latest": ["7.50", "8.1.7"], "supported_since": "7.0", "supported": true},
+        "Phpmyadmin": {"latest": ["4.0.10.16", "4.6.3"], "supported_since": "4.0", "supported": true},
+        "Mamboo": {"latest": ["4.6.5"], "supported_since": "", "supported": false}
+    }"#).unwrap();
+    assert!(check_release(&WebAppTypes::WordPress, "4.5.3", &releases) == (false, false));
+    assert!(check_release(&WebAppTypes::WordPress, "4.5.2", &releases) == (true, false));
+    assert!(check_release(&WebAppTypes::WordPress, "3.9.1", &releases) == (true, true));
+    assert!(check_release(&WebAppTypes::Drupal, "7.50", &releases) == (false, false));
+    assert!(check_release(&WebAppTypes::Drupal, "8.1.3", &releases) == (true, false));
+    assert!(check_release(&WebAppTypes::Drupal, "6.38", &releases) == (true, true));
+    assert!(check_release(&WebAppTypes::Phpmyadmin, "4.6.1", &releases) == (true, false));
+    assert!(check_release(&WebAppTypes::Phpmyadmin, "4.0.10.16", &releases) == (false, false));
+    assert!(check_release(&WebAppTypes::Mamboo, "4.6.5", &releases) == (true, true));
+    assert!(check_release(&WebAppTypes::Joomla, "1.5.26", &releases) == (false, false));
+    assert!(parse_releases("{\"WordPress\": {\"latest\": \"4.5.3\"}}").is_err());
+}
@@ -0,0 +1,801 @@
+/*
+    Practical subset of YARA rule syntax, evaluated without external libraries:
+        - rule modifiers: private, global (parsed, but ignored), tags
+        - meta: section with string, number and boolean values
+        - strings: text ("..." with: nocase, ascii, wide, fullword), hex ({ 4D 5A ?? [2-4] 90 }) and regex (/.../is)
+        - condition: $a, any of them, all of ($a, $b*), N of (..), filesize < 100KB, and, or, not, (..), true, false
+ */
+
+use process::*;
+
+use std::collections::{BTreeMap, HashMap};
+use regex::bytes;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HexToken {
+    Byte(u8, u8),           /* value, mask (0x0F, 0xF0 for nibble wildcards, 0x00 for "??") */
+    Jump(usize, usize),     /* min, max bytes to skip */
+}
+
+
+pub enum YaraString {
+    Text(Vec<Vec<u8>>, bool, bool), /* ascii and/or wide variants, nocase, fullword */
+    Hex(Vec<HexToken>),
+    Regex(bytes::Regex),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Quantifier {
+    Any,
+    All,
+    Count(usize),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Bool(bool),
+    String(String),                     /* string identifier, f.e.: "$a" */
+    Of(Quantifier, Vec<String>),        /* string identifiers, "*" suffix matches by prefix */
+    Filesize(String, u64),              /* comparison operator, size in bytes */
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+
+pub struct YaraRule {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub meta: BTreeMap<String, String>,
+    pub strings: Vec<(String, YaraString)>,
+    pub condition: Condition,
+}
+
+
+fn lowercase(byte: u8) -> u8 {
+    if byte >= b'A' && byte <= b'Z' { byte + 32 } else { byte }
+}
+
+
+fn is_word_byte(byte: u8) -> bool {
+    (byte as char).is_alphanumeric() || byte == b'_'
+}
+
+
+fn find_text(content: &[u8], needle: &[u8], nocase: bool, fullword: bool) -> Option<usize> {
+    if needle.is_empty() || needle.len() > content.len() {
+        return None
+    }
+    for start in 0..(content.len() - needle.len() + 1) {
+        let window = &content[start..start + needle.len()];
+        let equal = if nocase {
+            window.iter().zip(needle.iter()).all(|(a, b)| lowercase(*a) == lowercase(*b))
+        } else {
+            window == needle
+        };
+        if !equal {
+            continue
+        }
+        if fullword {
+            let end = start + needle.len();
+            if (start > 0 && is_word_byte(content[start - 1])) || (end < content.len() && is_word_byte(content[end])) {
+                continue
+            }
+        }
+        return Some(start)
+    }
+    None
+}
+
+
+fn hex_matches_at(content: &[u8], position: usize, tokens: &[HexToken]) -> bool {
+    match tokens.first() {
+        None => true,
+        Some(&HexToken::Byte(value, mask)) => {
+            position < content.len() &&
+                content[position] & mask == value & mask &&
+                hex_matches_at(content, position + 1, &tokens[1..])
+        },
+        Some(&HexToken::Jump(min, max)) => {
+            let available = content.len().saturating_sub(position);
+            let max = if max > available { available } else { max };
+            (min..max + 1).any(|skip| hex_matches_at(content, position + skip, &tokens[1..]))
+        },
+    }
+}
+
+
+fn find_hex(content: &[u8], tokens: &[HexToken]) -> Option<usize> {
+    (0..content.len()).find(|start| hex_matches_at(content, *start, tokens))
+}
+
+
+impl YaraString {
+    pub fn find(&self, content: &[u8]) -> Option<usize> {
+        match *self {
+            YaraString::Text(ref variants, nocase, fullword) => {
+                variants.iter().filter_map(|needle| find_text(content, needle, nocase, fullword)).min()
+            },
+            YaraString::Hex(ref tokens) => find_hex(content, tokens),
+            YaraString::Regex(ref regex) => regex.find(content).map(|(start, _)| start),
+        }
+    }
+}
+
+
+/* string identifier pattern: exact "$a" or prefix "$a*" */
+fn identifier_matches(pattern: &str, identifier: &str) -> bool {
+    if pattern.ends_with("*") {
+        identifier.starts_with(&pattern[..pattern.len() - 1])
+    } else {
+        pattern == identifier
+    }
+}
+
+
+impl Condition {
+    /* matches - offsets of strings found in content, keyed by string identifier */
+    pub fn evaluate(&self, matches: &HashMap<String, Option<usize>>, filesize: u64) -> bool {
+        match *self {
+            Condition::Bool(value) => value,
+            Condition::String(ref identifier) => match matches.get(identifier) {
+                Some(&Some(_)) => true,
+                _ => false,
+            },
+            Condition::Of(ref quantifier, ref patterns) => {
+                let selected: Vec<&Option<usize>> = matches
+                    .iter()
+                    .filter(|&(identifier, _)| patterns.iter().any(|pattern| identifier_matches(pattern, identifier)))
+                    .map(|(_, offset)| offset)
+                    .collect();
+                let found = selected.iter().filter(|offset| offset.is_some()).count();
+                match *quantifier {
+                    Quantifier::Any => found > 0,
+                    Quantifier::All => !selected.is_empty() && found == selected.len(),
+                    Quantifier::Count(count) => found >= count,
+                }
+            },
+            Condition::Filesize(ref operator, size) => match operator.as_str() {
+                "<" => filesize < size,
+                "<=" => filesize <= size,
+                ">" => filesize > size,
+                ">=" => filesize >= size,
+                "==" => filesize == size,
+                "!=" => filesize != size,
+                _ => false,
+            },
+            Condition::Not(ref condition) => !condition.evaluate(matches, filesize),
+            Condition::And(ref left, ref right) => left.evaluate(matches, filesize) && right.evaluate(matches, filesize),
+            Condition::Or(ref left, ref right) => left.evaluate(matches, filesize) || right.evaluate(matches, filesize),
+        }
+    }
+
+    fn identifiers(&self) -> Vec<String> {
+        match *self {
+            Condition::String(ref identifier) => vec!(identifier.clone()),
+            Condition::Of(_, ref patterns) => patterns.clone(),
+            Condition::Not(ref condition) => condition.identifiers(),
+            Condition::And(ref left, ref right) | Condition::Or(ref left, ref right) => {
+                let mut identifiers = left.identifiers();
+                identifiers.extend(right.identifiers());
+                identifiers
+            },
+            _ => vec!(),
+        }
+    }
+}
+
+
+impl YaraRule {
+    /* offset of first string match, if rule condition is satisfied */
+    pub fn find(&self, content: &[u8], filesize: u64) -> Option<usize> {
+        let mut matches = HashMap::new();
+        for &(ref identifier, ref string) in self.strings.iter() {
+            matches.insert(identifier.clone(), string.find(content));
+        }
+        if self.condition.evaluate(&matches, filesize) {
+            Some(matches.values().filter_map(|offset| *offset).min().unwrap_or(0))
+        } else {
+            None
+        }
+    }
+}
+
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    anonymous: usize, /* counter of anonymous strings: $ = "..." */
+}
+
+
+impl Parser {
+    fn new(source: &str) -> Parser {
+        Parser { chars: source.chars().collect(), position: 0, anonymous: 0 }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        let line = self.chars.iter().take(self.position).filter(|c| **c == '\n').count() + 1;
+        Err(format!("YARA parse error at line {}: {}", line, message))
+    }
+
+    fn current(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn following(&self) -> Option<char> {
+        self.chars.get(self.position + 1).cloned()
+    }
+
+    /* skips whitespaces and comments */
+    fn skip_blank(&mut self) -> Result<(), String> {
+        loop {
+            match (self.current(), self.following()) {
+                (Some(c), _) if c.is_whitespace() => self.position += 1,
+                (Some('/'), Some('/')) => {
+                    while self.current().is_some() && self.current() != Some('\n') {
+                        self.position += 1;
+                    }
+                },
+                (Some('/'), Some('*')) => {
+                    self.position += 2;
+                    while !(self.current() == Some('*') && self.following() == Some('/')) {
+                        if self.current().is_none() {
+                            return self.error("unterminated comment")
+                        }
+                        self.position += 1;
+                    }
+                    self.position += 2;
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<char>, String> {
+        try!(self.skip_blank());
+        Ok(self.current())
+    }
+
+    fn eat(&mut self, expected: char) -> Result<bool, String> {
+        if try!(self.peek()) == Some(expected) {
+            self.position += 1;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if try!(self.eat(expected)) {
+            Ok(())
+        } else {
+            self.error(&format!("expected: '{}'", expected))
+        }
+    }
+
+    fn peek_identifier(&mut self) -> Result<Option<String>, String> {
+        try!(self.skip_blank());
+        let identifier: String = self.chars[self.position..]
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || **c == '_')
+            .cloned()
+            .collect();
+        match identifier.chars().next() {
+            Some(first) if !first.is_digit(10) => Ok(Some(identifier)),
+            _ => Ok(None),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match try!(self.peek_identifier()) {
+            Some(identifier) => {
+                self.position += identifier.chars().count();
+                Ok(identifier)
+            },
+            None => self.error("expected identifier"),
+        }
+    }
+
+    /* consumes given keyword if it's next */
+    fn keyword(&mut self, word: &str) -> Result<bool, String> {
+        match try!(self.peek_identifier()) {
+            Some(ref identifier) if identifier == word => {
+                self.position += word.len();
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    fn string_identifier(&mut self, wildcard: bool) -> Result<String, String> {
+        if !try!(self.eat('$')) {
+            return self.error("expected string identifier")
+        }
+        let mut identifier = String::from("$");
+        while let Some(c) = self.current() {
+            if c.is_alphanumeric() || c == '_' {
+                identifier.push(c);
+                self.position += 1;
+            } else {
+                break
+            }
+        }
+        if wildcard && self.current() == Some('*') {
+            identifier.push('*');
+            self.position += 1;
+        }
+        Ok(identifier)
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        try!(self.skip_blank());
+        let hexadecimal = self.current() == Some('0') && self.following() == Some('x');
+        if hexadecimal {
+            self.position += 2;
+        }
+        let radix = if hexadecimal { 16 } else { 10 };
+        let digits: String = self.chars[self.position..].iter().take_while(|c| c.is_digit(radix)).cloned().collect();
+        if digits.is_empty() {
+            return self.error("expected number")
+        }
+        self.position += digits.len();
+        let value = match u64::from_str_radix(&digits, radix) {
+            Ok(value) => value,
+            Err(_) => return self.error(&format!("invalid number: {}", digits)),
+        };
+        let multiplier = if self.current() == Some('K') && self.following() == Some('B') {
+            self.position += 2;
+            1024
+        } else if self.current() == Some('M') && self.following() == Some('B') {
+            self.position += 2;
+            1024 * 1024
+        } else {
+            1
+        };
+        match value.checked_mul(multiplier) {
+            Some(value) => Ok(value),
+            None => self.error(&format!("number too large: {}", digits)),
+        }
+    }
+
+    fn text_literal(&mut self) -> Result<Vec<u8>, String> {
+        try!(self.expect('"'));
+        let mut bytes = vec!();
+        loop {
+            match self.current() {
+                None | Some('\n') => return self.error("unterminated string"),
+                Some('"') => {
+                    self.position += 1;
+                    return Ok(bytes)
+                },
+                Some('\\') => {
+                    self.position += 1;
+                    match self.current() {
+                        Some('n') => bytes.push(b'\n'),
+                        Some('t') => bytes.push(b'\t'),
+                        Some('r') => bytes.push(b'\r'),
+                        Some('"') => bytes.push(b'"'),
+                        Some('\\') => bytes.push(b'\\'),
+                        Some('x') => {
+                            let hex: String = self.chars[self.position + 1..].iter().take(2).cloned().collect();
+                            match u8::from_str_radix(&hex, 16) {
+                                Ok(byte) if hex.len() == 2 => bytes.push(byte),
+                                _ => return self.error(&format!("invalid escape: \\x{}", hex)),
+                            }
+                            self.position += 2;
+                        },
+                        _ => return self.error("invalid escape sequence"),
+                    }
+                    self.position += 1;
+                },
+                Some(c) => {
+                    bytes.extend_from_slice(c.to_string().as_bytes());
+                    self.position += 1;
+                },
+            }
+        }
+    }
+
+    fn hex_literal(&mut self) -> Result<Vec<HexToken>, String> {
+        try!(self.expect('{'));
+        let mut tokens = vec!();
+        loop {
+            match try!(self.peek()) {
+                None => return self.error("unterminated hex string"),
+                Some('}') => {
+                    self.position += 1;
+                    break
+                },
+                Some('[') => {
+                    self.position += 1;
+                    let range: String = self.chars[self.position..].iter().take_while(|c| **c != ']').cloned().collect();
+                    if self.position + range.chars().count() >= self.chars.len() {
+                        return self.error("unterminated jump")
+                    }
+                    self.position += range.chars().count() + 1;
+                    let bounds: Vec<&str> = range.split('-').map(|e| e.trim()).collect();
+                    let parse = |bound: &str, default: usize| -> Option<usize> {
+                        if bound.is_empty() { Some(default) } else { bound.parse::<usize>().ok() }
+                    };
+                    /* content is never read beyond read limit, so longer jumps would only slow down matching */
+                    let limit = config().read_limit as usize;
+                    let jump = match bounds.len() {
+                        1 => parse(bounds[0], 0).map(|exact| HexToken::Jump(exact, exact)),
+                        2 => match (parse(bounds[0], 0), parse(bounds[1], limit)) {
+                            (Some(min), Some(max)) if min <= max => Some(HexToken::Jump(min, if max > limit { limit } else { max })),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match jump {
+                        Some(jump) => tokens.push(jump),
+                        None => return self.error(&format!("invalid jump: [{}]", range)),
+                    }
+                },
+                Some('(') => return self.error("alternatives in hex strings are not supported"),
+                Some(high) => {
+                    let low = match self.following() {
+                        Some(low) => low,
+                        None => return self.error("unterminated hex string"),
+                    };
+                    let nibble = |c: char| -> Option<(u8, u8)> {
+                        if c == '?' { Some((0, 0)) } else { c.to_digit(16).map(|value| (value as u8, 0x0F)) }
+                    };
+                    match (nibble(high), nibble(low)) {
+                        (Some((high_value, high_mask)), Some((low_value, low_mask))) => {
+                            tokens.push(HexToken::Byte((high_value << 4) | low_value, (high_mask << 4) | low_mask));
+                        },
+                        _ => return self.error(&format!("invalid hex byte: {}{}", high, low)),
+                    }
+                    self.position += 2;
+                },
+            }
+        }
+        if tokens.is_empty() {
+            return self.error("empty hex string")
+        }
+        Ok(tokens)
+    }
+
+    fn regex_literal(&mut self) -> Result<bytes::Regex, String> {
+        try!(self.expect('/'));
+        let mut pattern = String::new();
+        loop {
+            match self.current() {
+                None | Some('\n') => return self.error("unterminated regular expression"),
+                Some('/') => {
+                    self.position += 1;
+                    break
+                },
+                Some('\\') if self.following() == Some('/') => {
+                    pattern.push('/');
+                    self.position += 2;
+                },
+                Some('\\') => {
+                    pattern.push('\\');
+                    match self.following() {
+                        Some(escaped) => pattern.push(escaped),
+                        None => return self.error("unterminated regular expression"),
+                    }
+                    self.position += 2;
+                },
+                Some(c) => {
+                    pattern.push(c);
+                    self.position += 1;
+                },
+            }
+        }
+        let mut flags = String::new();
+        while let Some(flag) = self.current() {
+            match flag {
+                'i' | 's' => flags.push(flag),
+                _ => break,
+            }
+            self.position += 1;
+        }
+        let pattern = if flags.is_empty() { pattern } else { format!("(?{}){}", flags, pattern) };
+        match bytes::Regex::new(&pattern) {
+            Ok(regex) => Ok(regex),
+            Err(err) => self.error(&format!("invalid regular expression: {}. Cause: {}", pattern, err)),
+        }
+    }
+
+    fn meta_section(&mut self) -> Result<BTreeMap<String, String>, String> {
+        let mut meta = BTreeMap::new();
+        while let Some(key) = try!(self.peek_identifier()) {
+            if key == "strings" || key == "condition" {
+                break
+            }
+            try!(self.identifier());
+            try!(self.expect('='));
+            let value = match try!(self.peek()) {
+                Some('"') => String::from_utf8_lossy(&try!(self.text_literal())).into_owned(),
+                Some(c) if c.is_digit(10) => try!(self.number()).to_string(),
+                _ => {
+                    if try!(self.keyword("true")) {
+                        String::from("true")
+                    } else if try!(self.keyword("false")) {
+                        String::from("false")
+                    } else {
+                        return self.error(&format!("invalid value of meta: {}", key))
+                    }
+                },
+            };
+            meta.insert(key, value);
+        }
+        Ok(meta)
+    }
+
+    fn strings_section(&mut self) -> Result<Vec<(String, YaraString)>, String> {
+        let mut strings = vec!();
+        while try!(self.peek()) == Some('$') {
+            let mut identifier = try!(self.string_identifier(false));
+            if identifier == "$" {
+                identifier = format!("$_anonymous{}", self.anonymous);
+                self.anonymous += 1;
+            }
+            try!(self.expect('='));
+            let string = match try!(self.peek()) {
+                Some('"') => {
+                    let text = try!(self.text_literal());
+                    let (mut nocase, mut ascii, mut wide, mut fullword) = (false, false, false, false);
+                    loop {
+                        if try!(self.keyword("nocase")) {
+                            nocase = true;
+                        } else if try!(self.keyword("ascii")) {
+                            ascii = true;
+                        } else if try!(self.keyword("wide")) {
+                            wide = true;
+                        } else if try!(self.keyword("fullword")) {
+                            fullword = true;
+                        } else {
+                            break
+                        }
+                    }
+                    let mut variants = vec!();
+                    if ascii || !wide {
+                        variants.push(text.clone());
+                    }
+                    if wide {
+                        variants.push(text.iter().flat_map(|byte| vec!(*byte, 0u8)).collect());
+                    }
+                    YaraString::Text(variants, nocase, fullword)
+                },
+                Some('{') => YaraString::Hex(try!(self.hex_literal())),
+                Some('/') => YaraString::Regex(try!(self.regex_literal())),
+                _ => return self.error(&format!("invalid value of string: {}", identifier)),
+            };
+            if strings.iter().any(|&(ref defined, _)| *defined == identifier) {
+                return self.error(&format!("duplicated string identifier: {}", identifier))
+            }
+            strings.push((identifier, string));
+        }
+        Ok(strings)
+    }
+
+    fn or_expression(&mut self) -> Result<Condition, String> {
+        let mut left = try!(self.and_expression());
+        while try!(self.keyword("or")) {
+            let right = try!(self.and_expression());
+            left = Condition::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and_expression(&mut self) -> Result<Condition, String> {
+        let mut left = try!(self.not_expression());
+        while try!(self.keyword("and")) {
+            let right = try!(self.not_expression());
+            left = Condition::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn not_expression(&mut self) -> Result<Condition, String> {
+        if try!(self.keyword("not")) {
+            Ok(Condition::Not(Box::new(try!(self.not_expression()))))
+        } else {
+            self.primary_expression()
+        }
+    }
+
+    fn of_expression(&mut self, quantifier: Quantifier) -> Result<Condition, String> {
+        if !try!(self.keyword("of")) {
+            return self.error("expected: 'of'")
+        }
+        if try!(self.keyword("them")) {
+            return Ok(Condition::Of(quantifier, vec!(String::from("$*"))))
+        }
+        try!(self.expect('('));
+        let mut patterns = vec!(try!(self.string_identifier(true)));
+        while try!(self.eat(',')) {
+            patterns.push(try!(self.string_identifier(true)));
+        }
+        try!(self.expect(')'));
+        Ok(Condition::Of(quantifier, patterns))
+    }
+
+    fn primary_expression(&mut self) -> Result<Condition, String> {
+        match try!(self.peek()) {
+            Some('(') => {
+                self.position += 1;
+                let condition = try!(self.or_expression());
+                try!(self.expect(')'));
+                Ok(condition)
+            },
+            Some('$') => Ok(Condition::String(try!(self.string_identifier(false)))),
+            Some(c) if c.is_digit(10) => {
+                let count = try!(self.number());
+                self.of_expression(Quantifier::Count(count as usize))
+            },
+            _ => {
+                if try!(self.keyword("true")) {
+                    Ok(Condition::Bool(true))
+                } else if try!(self.keyword("false")) {
+                    Ok(Condition::Bool(false))
+                } else if try!(self.keyword("any")) {
+                    self.of_expression(Quantifier::Any)
+                } else if try!(self.keyword("all")) {
+                    self.of_expression(Quantifier::All)
+                } else if try!(self.keyword("filesize")) {
+                    try!(self.skip_blank());
+                    let operator: String = self.chars[self.position..].iter().take_while(|c| "<>=!".contains(**c)).cloned().collect();
+                    match operator.as_str() {
+                        "<" | "<=" | ">" | ">=" | "==" | "!=" => {},
+                        _ => return self.error(&format!("invalid filesize operator: '{}'", operator)),
+                    }
+                    self.position += operator.len();
+                    let size = try!(self.number());
+                    Ok(Condition::Filesize(operator, size))
+                } else {
+                    self.error("unsupported condition")
+                }
+            },
+        }
+    }
+
+    fn rule(&mut self) -> Result<YaraRule, String> {
+        while try!(self.keyword("private")) || try!(self.keyword("global")) {}
+        if !try!(self.keyword("rule")) {
+            return self.error("expected: 'rule'")
+        }
+        let name = try!(self.identifier());
+        let mut tags = vec!();
+        if try!(self.eat(':')) {
+            while let Some(tag) = try!(self.peek_identifier()) {
+                try!(self.identifier());
+                tags.push(tag);
+            }
+        }
+        try!(self.expect('{'));
+        let mut meta = BTreeMap::new();
+        let mut strings = vec!();
+        if try!(self.keyword("meta")) {
+            try!(self.expect(':'));
+            meta = try!(self.meta_section());
+        }
+        if try!(self.keyword("strings")) {
+            try!(self.expect(':'));
+            strings = try!(self.strings_section());
+        }
+        if !try!(self.keyword("condition")) {
+            return self.error(&format!("rule: {} has no condition", name))
+        }
+        try!(self.expect(':'));
+        let condition = try!(self.or_expression());
+        try!(self.expect('}'));
+
+        for identifier in condition.identifiers() {
+            if !strings.iter().any(|&(ref defined, _)| identifier_matches(&identifier, defined)) {
+                return self.error(&format!("undefined string: {} used in rule: {}", identifier, name))
+            }
+        }
+        Ok(YaraRule { name: name, tags: tags, meta: meta, strings: strings, condition: condition })
+    }
+}
+
+
+pub fn parse_yara(source: &str) -> Result<Vec<YaraRule>, String> {
+    let mut parser = Parser::new(source);
+    let mut rules = vec!();
+    while try!(parser.peek()).is_some() {
+        if try!(parser.keyword("import")) || try!(parser.keyword("include")) {
+            return parser.error("modules and includes are not supported")
+        }
+        rules.push(try!(parser.rule()));
+    }
+    Ok(rules)
+}
+
+
+#[cfg(test)]
+#[test]
+fn parse_yara_test() {
+    let rules = parse_yara(r#"
+        /* multi line
+           comment */
+        rule php_webshell : php shell {
+            meta:
+                description = "Generic PHP web shell"
+                severity = "Critical"
+                score = 80
+                verified = true
+            strings:
+                $eval = "eval(" nocase
+                $input = /\$_(POST|GET|REQUEST)\[/
+                $b64 = "base64_decode" fullword
+                $ = { 3C 3F 70 68 70 }  // <?php
+            condition:
+                $eval and ($input or $b64) and any of them and filesize < 1MB
+        }
+
+        private rule elf_dropper {
+            strings:
+                $magic = { 7F 45 4C 46 ?? 0? [2-4] 00 }
+                $w = "dropper" wide ascii
+            condition:
+                all of ($magic, $w*) and not filesize > 2KB
+        }
+
+        rule two_of_three { strings: $a = "a1" $b = "b2" $c = "c3" condition: 2 of them }
+    "#).unwrap();
+    assert!(rules.len() == 3);
+    assert!(rules[0].name == "php_webshell");
+    assert!(rules[0].tags == vec!(String::from("php"), String::from("shell")));
+    assert!(rules[0].meta.get("severity") == Some(&String::from("Critical")));
+    assert!(rules[0].meta.get("score") == Some(&String::from("80")));
+    assert!(rules[0].strings.len() == 4);
+
+    let check = |rule: &YaraRule, content: &[u8]| rule.find(content, content.len() as u64);
+    assert!(check(&rules[0], b"<?php EVAL($_POST['x']);") == Some(0));
+    assert!(check(&rules[0], b"<?php eval(base64_decodes('x'));") == None);
+    assert!(check(&rules[0], b"<?php echo 'eval(';") == None);
+
+    assert!(check(&rules[1], b"\x7fELF\x02\x01\x00\x00\x00d\x00r\x00o\x00p\x00p\x00e\x00r\x00") == Some(0));
+    assert!(check(&rules[1], b"\x7fELF\x02\x21\x00\x00\x00 dropper") == None);
+
+    assert!(check(&rules[2], b"a1 c3") == Some(0));
+    assert!(check(&rules[2], b"b2 only") == None);
+
+    let unbounded = parse_yara("rule a { strings: $a = { 4D [4-] 5A } condition: $a }").unwrap();
+    match unbounded[0].strings[0].1 {
+        YaraString::Hex(ref tokens) => assert!(tokens[1] == HexToken::Jump(4, config().read_limit as usize)),
+        _ => panic!("Hex string expected"),
+    }
+
+    for invalid in vec!(
+        "rule a { condition: $a }",
+        "rule a { strings: $a = \"x\" condition: }",
+        "rule a { strings: $a = { 4D ( 5A | 90 ) } condition: $a }",
+        "rule a { strings: $a = /(/ condition: $a }",
+        "import \"pe\" rule a { condition: true }",
+        "rule a { strings: $a = \"x\" $a = \"y\" condition: $a }",
+        "rule a { condition: filesize =< 10 }",
+        "rule { condition: true }",
+        "rule a { condition: filesize < 99999999999999999MB }",
+    ) {
+        assert!(parse_yara(invalid).is_err(), invalid);
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn parse_yara_unterminated_test() {
+    for (malformed, expected) in vec!(
+        ("rule /*", "unterminated comment"),
+        ("rule a { condition: true } /* never closed *", "unterminated comment"),
+        ("rule a { condition: filesize < /* 1", "unterminated comment"),
+        ("rule a { strings: $a = { 4D [2-", "unterminated jump"),
+        ("rule a { strings: $a = { 4D [", "unterminated jump"),
+    ) {
+        match parse_yara(malformed) {
+            Err(err) => assert!(err.contains(expected), format!("{}: {}", malformed, err)),
+            Ok(_) => panic!("Malformed rule parsed: {}", malformed),
+        }
+    }
+}